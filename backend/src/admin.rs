@@ -0,0 +1,175 @@
+use actix::{ActorContext, Addr, Handler, Message, ResponseFuture};
+use actix_web::{HttpRequest, HttpResponse, web::{Data, Path}};
+use actix_web::http::header;
+use tracing::{info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use crate::audit::{ADMIN_ACTOR, AuditAction, AuditEvent};
+use crate::game::{Game, GameManager};
+use crate::json::JsonValue;
+use crate::tools::{client_address, Identifier};
+
+/// The token that must be provided to use the admin API. The API is disabled
+/// when no token is configured.
+#[derive(Debug, Clone, Default)]
+pub struct AdminToken {
+    digest: Option<Vec<u8>>,
+}
+
+impl AdminToken {
+    pub fn from_env() -> AdminToken {
+        let token = std::env::var("QUIZLER_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        AdminToken {
+            digest: token.map(|token| Sha256::digest(token.as_bytes()).to_vec()),
+        }
+    }
+
+    /// Checks the bearer token of the provided request returning the response
+    /// to reject it with if it isn't allowed. Both tokens are hashed before
+    /// comparing so that the comparison takes the same time regardless of how
+    /// much of the token matches.
+    pub(crate) fn reject(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        let Some(digest) = &self.digest else {
+            return Some(HttpResponse::NotFound().finish());
+        };
+        let provided = req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| Sha256::digest(token.trim().as_bytes()));
+        let matches = provided.is_some_and(|provided| {
+            provided.iter().zip(digest).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+        });
+        if matches {
+            None
+        } else {
+            warn!("Rejected admin API request from {:?}", client_address(req));
+            Some(HttpResponse::Unauthorized().json(json!({ "error": "Invalid admin token" })))
+        }
+    }
+}
+
+/// Actions that can be taken through the admin API. The game manager passes
+/// them on to the games which they are for.
+#[derive(Message, Clone)]
+#[rtype(result = "Result<Value, &'static str>")]
+pub enum AdminAction {
+    ListGames,
+    GetGame { id: Identifier },
+    EndGame { id: Identifier },
+    KickPlayer { id: Identifier, player: Identifier },
+}
+
+fn game_summary(game: &Game) -> Value {
+    json!({
+        "id": game.id,
+        "title": game.title,
+        "state": game.state.to_json(),
+        "players": game.players.len(),
+        "questions": game.questions.len(),
+        "question": game.question_index,
+    })
+}
+
+fn game_details(game: &Game) -> Value {
+    let mut value = game_summary(game);
+    value["players"] = game.players.values()
+        .map(|player| json!({
+            "id": player.id,
+            "name": player.name,
+            "score": player.score,
+            "answers": player.answers.len(),
+        }))
+        .collect();
+    value["bans"] = game.bans.iter().map(|ban| ban.as_data().to_json()).collect();
+    value
+}
+
+impl Handler<AdminAction> for GameManager {
+    type Result = ResponseFuture<Result<Value, &'static str>>;
+
+    fn handle(&mut self, msg: AdminAction, _ctx: &mut Self::Context) -> Self::Result {
+        match &msg {
+            AdminAction::ListGames => {
+                let games: Vec<Addr<Game>> = self.games.values().cloned().collect();
+                Box::pin(async move {
+                    let mut summaries = Vec::with_capacity(games.len());
+                    for game in games {
+                        // Games that stop before responding are left out
+                        if let Ok(Ok(summary)) = game.send(AdminAction::ListGames).await {
+                            summaries.push(summary);
+                        }
+                    }
+                    Ok(Value::Array(summaries))
+                })
+            }
+            AdminAction::GetGame { id }
+            | AdminAction::EndGame { id }
+            | AdminAction::KickPlayer { id, .. } => {
+                let game = self.games.get(id).cloned();
+                Box::pin(async move {
+                    match game {
+                        Some(game) => game.send(msg).await.unwrap_or(Err("That game doesn't exist")),
+                        None => Err("That game doesn't exist"),
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl Handler<AdminAction> for Game {
+    type Result = Result<Value, &'static str>;
+
+    fn handle(&mut self, msg: AdminAction, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            AdminAction::ListGames => Ok(game_summary(self)),
+            AdminAction::GetGame { .. } => Ok(game_details(self)),
+            AdminAction::EndGame { .. } => {
+                info!("Game {} ({}) was ended by an administrator", self.title, self.id);
+                self.services.audit.record(AuditEvent::new(&self.id, ADMIN_ACTOR, AuditAction::GameEnded));
+                self.end("Game ended by an administrator.");
+                ctx.stop();
+                Ok(json!({ "ended": self.id }))
+            }
+            AdminAction::KickPlayer { player, .. } => {
+                let player = self.kick_player(player).ok_or("That player is not in the game.")?;
+                info!("Player {} ({}) was kicked from game {} by an administrator", player.name, player.id, self.id);
+                self.services.audit.record(AuditEvent::new(&self.id, ADMIN_ACTOR, AuditAction::PlayerKicked)
+                    .target(&player.id)
+                    .detail(player.name.clone()));
+                Ok(json!({ "kicked": player.id }))
+            }
+        }
+    }
+}
+
+async fn run(req: HttpRequest, token: Data<AdminToken>, manager: Data<Addr<GameManager>>, action: AdminAction) -> HttpResponse {
+    if let Some(response) = token.reject(&req) {
+        return response;
+    }
+    match manager.send(action).await {
+        Ok(Ok(value)) => HttpResponse::Ok().json(value),
+        Ok(Err(reason)) => HttpResponse::NotFound().json(json!({ "error": reason })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_games(req: HttpRequest, token: Data<AdminToken>, manager: Data<Addr<GameManager>>) -> HttpResponse {
+    run(req, token, manager, AdminAction::ListGames).await
+}
+
+pub async fn get_game(req: HttpRequest, path: Path<String>, token: Data<AdminToken>, manager: Data<Addr<GameManager>>) -> HttpResponse {
+    run(req, token, manager, AdminAction::GetGame { id: path.into_inner() }).await
+}
+
+pub async fn end_game(req: HttpRequest, path: Path<String>, token: Data<AdminToken>, manager: Data<Addr<GameManager>>) -> HttpResponse {
+    run(req, token, manager, AdminAction::EndGame { id: path.into_inner() }).await
+}
+
+pub async fn kick_player(req: HttpRequest, path: Path<(String, String)>, token: Data<AdminToken>, manager: Data<Addr<GameManager>>) -> HttpResponse {
+    let (id, player) = path.into_inner();
+    run(req, token, manager, AdminAction::KickPlayer { id, player }).await
+}
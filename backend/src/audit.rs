@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{HttpRequest, HttpResponse, web::{self, Data, Query}};
+use tracing::error;
+use serde_json::{json, Value};
+use crate::admin::AdminToken;
+use crate::tools::{Identifier, session_hash};
+
+/// The actor recorded for actions taken through the admin API
+pub const ADMIN_ACTOR: &str = "admin";
+/// The actor recorded for actions taken by the server itself
+pub const SERVER_ACTOR: &str = "server";
+
+/// The privileged actions which are recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    GameCreated,
+    GameStarted,
+    QuestionSkipped,
+    PlayerKicked,
+    PlayerBanned,
+    PlayerUnbanned,
+    GameEnded,
+}
+
+impl AuditAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::GameCreated => "game_created",
+            AuditAction::GameStarted => "game_started",
+            AuditAction::QuestionSkipped => "question_skipped",
+            AuditAction::PlayerKicked => "player_kicked",
+            AuditAction::PlayerBanned => "player_banned",
+            AuditAction::PlayerUnbanned => "player_unbanned",
+            AuditAction::GameEnded => "game_ended",
+        }
+    }
+}
+
+/// A single entry in the audit log. The actor is the hashed session id of the
+/// host or one of the admin and server actors.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub game: Identifier,
+    pub actor: String,
+    pub action: AuditAction,
+    /// The player the action was taken against
+    pub target: Option<Identifier>,
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(game: &str, actor: &str, action: AuditAction) -> AuditEvent {
+        AuditEvent {
+            game: game.to_string(),
+            actor: actor.to_string(),
+            action,
+            target: None,
+            detail: None,
+        }
+    }
+
+    /// Creates an event for an action taken by the host with the provided
+    /// session. Only a hash of the session is recorded as the session id
+    /// itself authorizes the host.
+    pub fn by_session(game: &str, session: &str, action: AuditAction) -> AuditEvent {
+        AuditEvent::new(game, &session_hash(session), action)
+    }
+
+    pub fn target(mut self, target: &str) -> AuditEvent {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> AuditEvent {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Messages handled by the thread writing the audit log
+enum WriterMessage {
+    Line(String),
+    /// Replies once every line sent before it has been written
+    Flush(Sender<()>),
+}
+
+/// Append only log of the privileged actions taken in games. Events are
+/// stored as one JSON object per line so the file can only ever grow. Lines
+/// are written in order by a dedicated thread so that recording an event
+/// never blocks the game manager or the games.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    writer: Sender<WriterMessage>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> AuditLog {
+        let (writer, messages) = mpsc::channel();
+        let writer_path = path.clone();
+        let spawned = thread::Builder::new()
+            .name(String::from("audit-log"))
+            .spawn(move || AuditLog::write_lines(&writer_path, messages));
+        if let Err(err) = spawned {
+            error!("Failed to start the audit log writer: {}", err);
+        }
+        AuditLog {
+            path,
+            writer,
+        }
+    }
+
+    pub fn from_env() -> AuditLog {
+        let path = std::env::var("QUIZLER_AUDIT_LOG").unwrap_or_else(|_| String::from("audit.log"));
+        AuditLog::new(PathBuf::from(path))
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Writes the lines sent to the writer thread until every sender has been
+    /// dropped. The file is opened when the first line is written.
+    fn write_lines(path: &Path, messages: Receiver<WriterMessage>) {
+        let mut file: Option<File> = None;
+        for message in messages {
+            let line = match message {
+                WriterMessage::Line(line) => line,
+                WriterMessage::Flush(done) => {
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+            let result = match file.as_mut() {
+                Some(file) => writeln!(file, "{}", line),
+                None => AuditLog::open(path).and_then(|mut opened| {
+                    writeln!(opened, "{}", line)?;
+                    file = Some(opened);
+                    Ok(())
+                }),
+            };
+            if let Err(err) = result {
+                error!("Failed to write to the audit log: {}", err);
+            }
+        }
+    }
+
+    /// Appends the provided event to the log. Failures are logged rather than
+    /// returned so that they never stop the action itself.
+    pub fn record(&self, event: AuditEvent) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u64)
+            .unwrap_or_default();
+        let line = json!({
+            "time": time,
+            "game": event.game,
+            "actor": event.actor,
+            "action": event.action.name(),
+            "target": event.target,
+            "detail": event.detail,
+        }).to_string();
+        if self.writer.send(WriterMessage::Line(line)).is_err() {
+            error!("Failed to write to the audit log: the writer isn't running");
+        }
+    }
+
+    /// Waits until every event recorded so far has been written to the file
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.writer.send(WriterMessage::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Reads the most recent events optionally only including those for the
+    /// provided game. Events are returned oldest first.
+    pub fn query(&self, game: Option<&str>, limit: usize) -> io::Result<Vec<Value>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut events = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let Ok(event) = serde_json::from_str::<Value>(&line?) else {
+                continue;
+            };
+            if game.is_none_or(|game| event["game"].as_str() == Some(game)) {
+                events.push(event);
+            }
+        }
+        let skip = events.len().saturating_sub(limit);
+        Ok(events.split_off(skip))
+    }
+}
+
+/// Admin API route handler for querying the audit log. Supports filtering
+/// by game with `?game=` and limiting the number of events with `?limit=`.
+pub async fn query_audit(
+    req: HttpRequest,
+    query: Query<HashMap<String, String>>,
+    token: Data<AdminToken>,
+    audit: Data<AuditLog>,
+) -> HttpResponse {
+    if let Some(response) = token.reject(&req) {
+        return response;
+    }
+    let game = query.get("game").map(|game| game.to_ascii_uppercase());
+    let limit = query.get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(100);
+    match web::block(move || audit.query(game.as_deref(), limit)).await {
+        Ok(Ok(events)) => HttpResponse::Ok().json(events),
+        Ok(Err(err)) => {
+            error!("Failed to read the audit log: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{Read, Write};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use crate::packets::DecodeError;
+
+/// Packets smaller than this aren't worth compressing
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+/// The largest size a compressed packet is allowed to expand to. This stops
+/// small packets from decompressing into huge amounts of memory.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Compresses the provided encoded packet using zlib returning None if the
+/// packet is too small or compressing it didn't make it any smaller.
+pub fn compress(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).ok()?;
+    let compressed = encoder.finish().ok()?;
+    if compressed.len() < bytes.len() {
+        Some(compressed)
+    } else {
+        None
+    }
+}
+
+/// Decompresses a zlib compressed packet
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| DecodeError::InvalidCompression)?;
+    if out.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        return Err(DecodeError::InvalidCompression);
+    }
+    Ok(out)
+}
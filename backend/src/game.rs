@@ -1,52 +1,242 @@
 use std::collections::HashMap;
-use std::sync::{Arc, mpsc, RwLock, TryLockResult};
+use std::future::{Future, ready};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use actix::*;
-use actix_web::web::Data;
-use log::{error, info};
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use actix_web::web::{self, Data};
+use tracing::{error, info};
 use wsbps::VarInt;
 use crate::Connection;
-use crate::packets::{ClientPackets, GameState, PlayerDataMode, QuestionData, ServerPackets, StateChange};
-use crate::packets::ServerPackets::PlayerData;
-use crate::socket::GameData;
-use crate::tools::{Identifier, random_identifier};
+use crate::audit::{AuditAction, AuditEvent, AuditLog, SERVER_ACTOR};
+use crate::images::{image_path, ImageLimits, ImageStore};
+use crate::library::{Quiz, QuizLibrary};
+use crate::logging::LogContext;
+use crate::metrics::Metrics;
+use crate::names::{name_key, NameError, NameFilter};
+use crate::packets::{BanData, Blob, ClientPackets, GameState, PlayerDataMode, QuestionData, ResultsFormat, ServerPackets, StateChange};
+use crate::registry::Registry;
+use crate::results::{AnswerRecord, GameResults, PlayerResult, ResultsStore};
+use crate::snapshot::SnapshotStore;
+use crate::socket::{GameData, Session};
+use crate::tools::{env_flag, env_value, Identifier, random_identifier, session_hash};
 
 pub type AnswerIndex = u8;
-pub type QuestionIndex = u8;
+/// The index of a question in a game. This isn't sent to clients so it's as
+/// wide as the list of questions and can't wrap around on long quizzes.
+pub type QuestionIndex = usize;
 
+/// Creates games and finds the game that a code belongs to. Every game is its
+/// own actor so the manager never holds any game state itself.
 pub struct GameManager {
-    pub games: Arc<RwLock<HashMap<Identifier, Game>>>,
+    pub games: HashMap<Identifier, Addr<Game>>,
+    pub name_filter: Arc<NameFilter>,
+    pub ban_by_ip: bool,
+    pub images: ImageStore,
+    pub image_limits: ImageLimits,
+    pub library: QuizLibrary,
+    pub results: ResultsStore,
+    pub metrics: Metrics,
+    pub snapshots: SnapshotStore,
+    pub audit: AuditLog,
+    pub registry: Registry,
+    /// Whether the server is shutting down and refusing new games
+    pub shutting_down: bool,
+    /// The threads that games are spread across
+    arbiters: Vec<ArbiterHandle>,
+    next_arbiter: usize,
 }
 
+/// The shared services used by games
+#[derive(Debug, Clone)]
+pub struct GameServices {
+    pub manager: Addr<GameManager>,
+    pub name_filter: Arc<NameFilter>,
+    pub ban_by_ip: bool,
+    pub images: ImageStore,
+    pub results: ResultsStore,
+    pub metrics: Metrics,
+    pub audit: AuditLog,
+}
 
-impl GameManager {
-    const SLEEP_INTERVAL: Duration = Duration::from_secs(1);
-    const START_DELAY: Duration = Duration::from_secs(5);
-    const QUESTION_TIME: Duration = Duration::from_secs(10);
-    const MARK_TIME: Duration = Duration::from_secs(3);
-    const BONUS_TIME: Duration = Duration::from_secs(5);
-
-    const POINTS: u32 = 100;
-    const BONUS_POINTS: f32 = 200.0;
 
-    pub fn new() -> Data<Addr<GameManager>> {
+impl GameManager {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(images: ImageStore, library: QuizLibrary, image_limits: ImageLimits, results: ResultsStore, metrics: Metrics, snapshots: SnapshotStore, audit: AuditLog, registry: Registry) -> Data<Addr<GameManager>> {
         Data::new(GameManager {
-            games: Arc::new(RwLock::new(HashMap::new()))
+            games: HashMap::new(),
+            name_filter: Arc::new(NameFilter::from_env()),
+            ban_by_ip: env_flag("QUIZLER_BAN_BY_IP"),
+            images,
+            image_limits,
+            library,
+            results,
+            metrics,
+            snapshots,
+            audit,
+            registry,
+            shutting_down: false,
+            arbiters: (0..env_value("QUIZLER_GAME_THREADS", default_threads()).max(1))
+                .map(|_| Arbiter::new().handle())
+                .collect(),
+            next_arbiter: 0,
         }.start())
     }
+
+    /// Starts the actor for a game on the next of the game threads
+    fn spawn(&mut self, game: Game) -> Addr<Game> {
+        let arbiter = &self.arbiters[self.next_arbiter % self.arbiters.len()];
+        self.next_arbiter = self.next_arbiter.wrapping_add(1);
+        Game::start_in_arbiter(arbiter, move |_| game)
+    }
+
+    fn services(&self, ctx: &<Self as Actor>::Context) -> GameServices {
+        GameServices {
+            manager: ctx.address(),
+            name_filter: self.name_filter.clone(),
+            ban_by_ip: self.ban_by_ip,
+            images: self.images.clone(),
+            results: self.results.clone(),
+            metrics: self.metrics.clone(),
+            audit: self.audit.clone(),
+        }
+    }
+
+    /// Creates a new game hosted by the provided connection using questions
+    /// that have already had their images processed. The game code is
+    /// claimed from the registry off the manager as the registry may be
+    /// shared storage.
+    fn create_game(&mut self, host: Addr<Connection>, host_session: Identifier, title: String, questions: Vec<QuestionData>) -> ResponseActFuture<Self, ClientAction> {
+        if self.shutting_down {
+            return Box::pin(ready(ClientAction::Error("The server is restarting. Please try again shortly")).into_actor(self));
+        }
+        let registry = self.registry.clone();
+        Box::pin(web::block(move || registry.claim_new(Game::ID_LENGTH))
+            .into_actor(self)
+            .map(move |claimed, act, ctx| match claimed {
+                Ok(Ok(id)) if act.shutting_down => {
+                    act.release(id);
+                    ClientAction::Error("The server is restarting. Please try again shortly")
+                }
+                Ok(Ok(id)) => act.start_game(ctx, id, host, host_session, title, questions),
+                _ => ClientAction::Error("Failed to create game. Please try again"),
+            }))
+    }
+
+    /// Starts a new game using the claimed game code
+    fn start_game(&mut self, ctx: &<Self as Actor>::Context, id: Identifier, host: Addr<Connection>, host_session: Identifier, title: String, questions: Vec<QuestionData>) -> ClientAction {
+        let mut q = Vec::with_capacity(questions.len());
+        for mut que in questions {
+            let image = if que.image.is_empty() {
+                None
+            } else {
+                let data = std::mem::take(&mut que.image.0);
+                Some(self.images.insert(&id, que.image_type.clone(), data))
+            };
+            q.push(Question {
+                data: que,
+                image,
+                start_time: Instant::now(),
+            })
+        }
+        let game = Game {
+            host: ClientAddr::from(host),
+            host_session,
+            id: id.clone(),
+            title: title.clone(),
+            questions: q,
+            players: HashMap::new(),
+            bans: Vec::new(),
+            departed: Vec::new(),
+            state: GameState::Waiting,
+            timer: GameTimer::new(),
+            question_index: 0,
+            marking: false,
+            results: None,
+            restored: None,
+            services: self.services(ctx),
+        };
+        self.audit.record(AuditEvent::by_session(&id, &game.host_session, AuditAction::GameCreated).detail(title.clone()));
+        let game = self.spawn(game);
+        self.games.insert(id.clone(), game.clone());
+        ClientAction::CreatedGame {
+            id,
+            title,
+            game,
+        }
+    }
+
+    /// Releases the game code in the registry off the manager
+    fn release(&self, id: Identifier) {
+        let registry = self.registry.clone();
+        actix_web::rt::spawn(web::block(move || registry.release(&id)));
+    }
+
+    /// Processes the question images and then creates the game. Processing
+    /// images is slow so it happens off the manager which continues handling
+    /// other clients in the meantime.
+    fn process_and_create(&mut self, host: Addr<Connection>, session: Session, title: String, mut questions: Vec<QuestionData>) -> ResponseActFuture<Self, ClientAction> {
+        if self.shutting_down {
+            return Box::pin(ready(ClientAction::Error("The server is restarting. Please try again shortly")).into_actor(self));
+        }
+        let image_limits = self.image_limits;
+        Box::pin(web::block(move || image_limits.process_all(&mut questions).map(|_| questions))
+            .into_actor(self)
+            .then(move |processed, act, _ctx| match processed {
+                Ok(Ok(questions)) => act.create_game(host, session.id, title, questions),
+                Ok(Err(err)) => Box::pin(ready(ClientAction::Error(err.reason())).into_actor(act)),
+                Err(_) => Box::pin(ready(ClientAction::Error("Failed to create game. Please try again")).into_actor(act)),
+            }))
+    }
+
+    /// Saves the quiz to the library once its images have been processed.
+    /// Both happen off the manager as they're slow.
+    fn save_quiz(&mut self, title: String, mut questions: Vec<QuestionData>) -> ResponseActFuture<Self, ClientAction> {
+        let image_limits = self.image_limits;
+        let library = self.library.clone();
+        Box::pin(async move {
+            let saved = web::block(move || {
+                image_limits.process_all(&mut questions).map_err(|err| err.reason())?;
+                library.save(&Quiz { title, questions }).map_err(|err| err.reason())
+            }).await;
+            match saved {
+                Ok(Ok(code)) => ClientAction::Packet(ServerPackets::QuizSaved { code }),
+                Ok(Err(reason)) => ClientAction::Error(reason),
+                Err(_) => ClientAction::Error("Failed to access the quiz library"),
+            }
+        }.into_actor(self))
+    }
+
+    /// Loads the quiz from the library off the manager and then creates a
+    /// game from it. Library images were already processed when it was saved.
+    fn create_from_library(&mut self, host: Addr<Connection>, session: Session, code: String) -> ResponseActFuture<Self, ClientAction> {
+        let library = self.library.clone();
+        Box::pin(web::block(move || library.load(&code))
+            .into_actor(self)
+            .then(move |loaded, act, _ctx| match loaded {
+                Ok(Ok(quiz)) => act.create_game(host, session.id, quiz.title, quiz.questions),
+                Ok(Err(err)) => Box::pin(ready(ClientAction::Error(err.reason())).into_actor(act)),
+                Err(_) => Box::pin(ready(ClientAction::Error("Failed to access the quiz library")).into_actor(act)),
+            }))
+    }
 }
 
-#[derive(Message)]
-#[rtype(result = "()")]
-#[derive(Debug)]
-enum GameChangeType {
-    Remove,
-    Started,
-    SkipQuestion,
-    NextQuestion,
-    SyncTime { total: Duration, remaining: Duration },
-    Continue,
+/// The number of game threads used when QUIZLER_GAME_THREADS isn't set
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, usize::from)
+}
+
+/// The response to a packet for a game that doesn't exist
+fn missing_game(packet: &ClientPackets) -> ClientAction {
+    match packet {
+        ClientPackets::RequestGameState { .. } => ClientAction::Packet(ServerPackets::GameState {
+            state: GameState::DoesNotExist,
+        }),
+        ClientPackets::ResumeGame { .. } => ClientAction::Multiple(vec![
+            ClientAction::Packet(ServerPackets::Disconnect { reason: String::from("Your game has ended.") }),
+            ClientAction::Disconnect,
+        ]),
+        _ => ClientAction::Error("That game code doesn't exist"),
+    }
 }
 
 impl Actor for GameManager {
@@ -54,213 +244,332 @@ impl Actor for GameManager {
 
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(GameManager::SLEEP_INTERVAL, |act, _ctx| {
-            let arc = act.games.clone();
-            let mut games = arc.write().unwrap();
-            games.iter_mut().for_each(|(id, game)| game.sync());
-
-            // games.iter_mut()
-            //     .for_each(|(id, game)|{
-            //         game.timer.sync(game);
-            //
-            //     });
-            //
-            // let changes = games.par_iter()
-            //     .filter_map(|(id, mut game)| {
-            //
-            //         if game.state == GameState::Stopped {
-            //             Some((id, GameChangeType::Remove))
-            //         } else if game.state != GameState::Waiting {
-            //             let time = Instant::now();
-            //             let elapsed_since_sync = time - game.time.last_sync;
-            //             if game.state == GameState::Starting {
-            //                 Some(if elapsed_since_sync >= GameManager::START_DELAY {
-            //                     (id, GameChangeType::Started)
-            //                 } else {
-            //                     let remaining = time - game.time.game_start;
-            //                     (id, GameChangeType::SyncTime {
-            //                         total: GameManager::QUESTION_TIME,
-            //                         remaining,
-            //                     })
-            //                 })
-            //             } else {
-            //                 None
-            //             }
-            //         } else {
-            //             None
-            //         }
-            //     })
-            //     .collect::<Vec<(&Identifier, GameChangeType)>>();
-        });
+        let registry = self.registry.clone();
+        let snapshots = self.snapshots.clone();
+        let services = self.services(ctx);
+        // Clients wait until the restored games are running before the
+        // manager handles anything else
+        ctx.wait(web::block(move || {
+            registry.reset();
+            snapshots.restore(&services)
+                .into_iter()
+                .filter(|game| {
+                    let claimed = registry.claim(&game.id).unwrap_or(false);
+                    if !claimed {
+                        error!("Dropping restored game {} because its code is owned by another node", game.id);
+                        services.images.remove_game(&game.id);
+                    }
+                    claimed
+                })
+                .collect::<Vec<Game>>()
+        }).into_actor(self).map(|games, act, _ctx| {
+            for game in games.unwrap_or_default() {
+                let id = game.id.clone();
+                let game = act.spawn(game);
+                act.games.insert(id, game);
+            }
+        }));
     }
 }
 
+/// Sent by games once they have stopped so that their code can be reused
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct GameStopped {
+    pub id: Identifier,
+}
+
+impl Handler<GameStopped> for GameManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: GameStopped, _ctx: &mut Self::Context) -> Self::Result {
+        if self.games.remove(&msg.id).is_some() {
+            self.release(msg.id);
+        }
+    }
+}
+
+/// A packet from a client which isn't for the game the client is in. Packets
+/// which look up a game are passed on to that game.
 #[derive(Message)]
 #[rtype(result = "ClientAction")]
-pub enum ServerAction {
+pub struct ServerAction {
+    pub packet: ClientPackets,
+    pub ret: Addr<Connection>,
+    pub session: Session,
+}
+
+impl ServerAction {
+    /// The log context for handling this action
+    fn log_context(&self) -> LogContext {
+        LogContext {
+            session: Some(session_hash(&self.session.id)),
+            packet: Some(self.packet.name()),
+            ..LogContext::default()
+        }
+    }
+}
+
+/// Messages from connections to the game they are in
+#[derive(Message)]
+#[rtype(result = "ClientAction")]
+pub enum GameAction {
+    /// A packet looking up this game passed on by the game manager
     Packet {
         packet: ClientPackets,
         ret: Addr<Connection>,
+        session: Session,
     },
     DoStateChange {
         state: StateChange,
         game_data: GameData,
     },
     TryKick { id: Identifier, game_data: GameData },
-    None,
+    TryUnban { id: Identifier, game_data: GameData },
+    GetBans { game_data: GameData },
+    TryAnswer { answer: AnswerIndex, game_data: GameData },
+    GetResults { format: ResultsFormat, game_data: GameData },
+}
+
+impl GameAction {
+    /// The log context for handling this action
+    fn log_context(&self, game: &Identifier) -> LogContext {
+        let context = match self {
+            GameAction::Packet { packet, session, .. } => LogContext {
+                session: Some(session_hash(&session.id)),
+                packet: Some(packet.name()),
+                ..LogContext::default()
+            },
+            GameAction::DoStateChange { game_data, .. }
+            | GameAction::TryKick { game_data, .. }
+            | GameAction::TryUnban { game_data, .. }
+            | GameAction::GetBans { game_data }
+            | GameAction::TryAnswer { game_data, .. }
+            | GameAction::GetResults { game_data, .. } => game_data.log_context(),
+        };
+        LogContext { game: Some(game.clone()), ..context }
+    }
 }
 
 #[derive(Message, Clone)]
 #[rtype(result = "()")]
 pub enum ClientAction {
-    CreatedGame { id: Identifier, title: String },
-    NameTakenResult(bool),
+    CreatedGame { id: Identifier, title: String, game: Addr<Game> },
+    NameTakenResult(Option<&'static str>),
     Packet(ServerPackets),
     Error(&'static str),
-    JoinedGame { id: Identifier, player_id: Identifier, title: String },
-    StateChange(StateChange),
-    BeginKick(Identifier),
+    JoinedGame { id: Identifier, player_id: Identifier, title: String, game: Addr<Game> },
+    /// Resumed a restored game, the player id is None for the host
+    ResumedGame { id: Identifier, player_id: Option<Identifier>, title: String, game: Addr<Game> },
     Disconnect,
     Multiple(Vec<ClientAction>),
     None,
 }
 
 
-impl Handler<ServerAction> for GameManager {
-    type Result = MessageResult<ServerAction>;
+/// Sent when the server is shutting down. New games are refused and games
+/// that aren't in progress are ended. When forced every remaining game is
+/// ended saving the results of those in progress. Responds with the number
+/// of games which are still running.
+#[derive(Message, Clone, Copy)]
+#[rtype(result = "usize")]
+pub struct Shutdown {
+    pub force: bool,
+}
 
-    fn handle(&mut self, msg: ServerAction, ctx: &mut Self::Context) -> Self::Result {
-        MessageResult(match msg {
-            ServerAction::Packet { packet, ret } => match packet {
-                ClientPackets::CreateGame { title, questions } => {
-                    let mut id: Identifier;
-                    let mut games = self.games.write().unwrap();
-                    loop {
-                        id = random_identifier(Game::ID_LENGTH);
-                        if !games.contains_key(&id) { break; };
-                    };
-                    let mut q = Vec::with_capacity(questions.len());
-                    for que in questions {
-                        q.push(Question {
-                            data: que,
-                            start_time: Instant::now(),
-                        })
-                    }
-                    let game = Game {
-                        host: ret,
-                        id: id.clone(),
-                        title: title.clone(),
-                        questions: q,
-                        players: Arc::new(RwLock::new(HashMap::new())),
-                        state: GameState::Waiting,
-                        timer: GameTimer::new(),
-                    };
-                    games.insert(id.clone(), game);
-                    ClientAction::CreatedGame {
-                        id,
-                        title,
-                    }
+/// Requests the quiz of a game so that it can be exported as a quiz file
+#[derive(Message, Clone)]
+#[rtype(result = "Result<Quiz, &'static str>")]
+pub struct ExportGame {
+    pub id: Identifier,
+    pub session: Identifier,
+}
+
+impl Handler<ExportGame> for GameManager {
+    type Result = ResponseFuture<Result<Quiz, &'static str>>;
+
+    fn handle(&mut self, msg: ExportGame, _ctx: &mut Self::Context) -> Self::Result {
+        let game = self.games.get(&msg.id).cloned();
+        Box::pin(async move {
+            match game {
+                Some(game) => game.send(msg).await.unwrap_or(Err("That game code doesn't exist")),
+                None => Err("That game code doesn't exist"),
+            }
+        })
+    }
+}
+
+impl Handler<ExportGame> for Game {
+    type Result = Result<Quiz, &'static str>;
+
+    fn handle(&mut self, msg: ExportGame, _ctx: &mut Self::Context) -> Self::Result {
+        if self.host_session != msg.session {
+            return Err("You are not the host.");
+        }
+        let questions = self.questions
+            .iter()
+            .map(|question| {
+                let mut data = question.data.clone();
+                if let Some(image) = question.image.as_ref().and_then(|hash| self.services.images.get(&self.id, hash)) {
+                    data.image = Blob(image.data.to_vec());
                 }
-                ClientPackets::CheckNameTaken { id, name } => {
-                    let games = self.games.read().unwrap();
-                    let game = games.get(&id);
-                    match game {
-                        None => ClientAction::Error("That game code doesn't exist"),
-                        Some(game) => ClientAction::NameTakenResult(game.is_name_taken(&name))
-                    }
+                data
+            })
+            .collect();
+        Ok(Quiz { title: self.title.clone(), questions })
+    }
+}
+
+impl Handler<ServerAction> for GameManager {
+    type Result = ResponseActFuture<Self, ClientAction>;
+
+    fn handle(&mut self, msg: ServerAction, _ctx: &mut Self::Context) -> Self::Result {
+        let _log = msg.log_context().enter();
+        let ServerAction { packet, ret, session } = msg;
+        let packet = match packet {
+            ClientPackets::CreateGame { title, questions } => return self.process_and_create(ret, session, title, questions),
+            ClientPackets::SaveQuiz { title, questions } => return self.save_quiz(title, questions),
+            ClientPackets::CreateGameFromQuiz { code } => return self.create_from_library(ret, session, code),
+            packet => packet,
+        };
+        match &packet {
+            ClientPackets::CheckNameTaken { id, .. }
+            | ClientPackets::RequestGameState { id }
+            | ClientPackets::RequestJoin { id, .. }
+            | ClientPackets::ResumeGame { id } => match self.games.get(id) {
+                Some(game) => {
+                    let game = game.clone();
+                    let fallback = missing_game(&packet);
+                    Box::pin(async move {
+                        game.send(GameAction::Packet { packet, ret, session })
+                            .await
+                            .unwrap_or(fallback)
+                    }.into_actor(self))
                 }
-                ClientPackets::RequestGameState { id } => {
-                    let games = self.games.read().unwrap();
-                    let game = games.get(&id);
-                    ClientAction::Packet(ServerPackets::GameState {
-                        state: match game {
-                            None => GameState::DoesNotExist,
-                            Some(game) => game.state.clone()
+                // Games owned by another node are handled by that node
+                None => {
+                    let registry = self.registry.clone();
+                    let id = id.clone();
+                    Box::pin(async move {
+                        match web::block(move || registry.remote(&id).map(|node| (id, node))).await {
+                            Ok(Some((id, node))) => ClientAction::Packet(ServerPackets::Redirect { id, url: node.url }),
+                            _ => missing_game(&packet),
                         }
-                    })
+                    }.into_actor(self))
                 }
+            },
+            _ => Box::pin(ready(ClientAction::None).into_actor(self)),
+        }
+    }
+}
+
+impl Handler<GameAction> for Game {
+    type Result = MessageResult<GameAction>;
+
+    fn handle(&mut self, msg: GameAction, ctx: &mut Self::Context) -> Self::Result {
+        let _log = msg.log_context(&self.id).enter();
+        MessageResult(match msg {
+            GameAction::Packet { packet, ret, session } => match packet {
+                ClientPackets::CheckNameTaken { name, .. } => ClientAction::NameTakenResult(
+                    self.check_name(&name)
+                        .err()
+                        .map(|err| err.reason())
+                ),
+                ClientPackets::RequestGameState { .. } => ClientAction::Packet(ServerPackets::GameState {
+                    state: self.state.clone()
+                }),
                 ClientPackets::RequestJoin { id, name } => {
-                    let mut games = self.games.write().unwrap();
-                    let game = games.get_mut(&id);
-                    match game {
-                        None => ClientAction::Error("That game code doesn't exist"),
-                        Some(game) => {
-                            if game.is_name_taken(&name) {
-                                ClientAction::Error("That name is already in use")
-                            } else {
-                                let player_id = game.new_player(name, ret);
-                                ClientAction::JoinedGame { id, player_id, title: game.title.clone() }
-                            }
+                    if self.is_banned(&session) {
+                        return MessageResult(ClientAction::Error("You have been banned from this game"));
+                    }
+                    match self.check_name(&name) {
+                        Err(err) => ClientAction::Error(err.reason()),
+                        Ok(name) => {
+                            let player_id = self.new_player(name, ret, session);
+                            ClientAction::JoinedGame { id, player_id, title: self.title.clone(), game: ctx.address() }
                         }
                     }
                 }
-                ClientPackets::StateChange { state } => ClientAction::StateChange(state),
-                ClientPackets::Kick { id } => ClientAction::BeginKick(id),
+                ClientPackets::ResumeGame { .. } => match self.resume(ctx, ret, &session) {
+                    Some(action) => action,
+                    None => missing_game(&packet),
+                },
                 _ => ClientAction::None
             }
-            ServerAction::DoStateChange { state, game_data } => {
+            GameAction::DoStateChange { state, game_data } => {
                 match state {
-                    StateChange::Start => {
-                        if game_data.game_id.is_none() {
-                            ClientAction::Error("You are not in a game.")
-                        } else {
-                            let mut games = self.games.write().unwrap();
-                            let game = games.get_mut(&game_data.game_id.unwrap());
-                            match game {
-                                None => ClientAction::Multiple(vec![
-                                    ClientAction::Error("You are not in a game."),
-                                    ClientAction::Disconnect,
-                                ]),
-                                Some(game) => {
-                                    game.state = GameState::Starting;
-                                    game.timer.track(GameManager::START_DELAY);
-                                    game.broadcast(ServerPackets::GameState { state: GameState::Starting });
-                                    ClientAction::None
-                                }
-                            }
-                        }
-                    }
-                    StateChange::Skip => ClientAction::None,
+                    StateChange::Start => self.with_host(&game_data, |game| {
+                        game.state = GameState::Starting;
+                        game.timer.track(Game::START_DELAY);
+                        game.broadcast(ServerPackets::GameState { state: GameState::Starting });
+                        game.services.audit.record(AuditEvent::by_session(&game.id, &game_data.session, AuditAction::GameStarted));
+                        ClientAction::None
+                    }),
+                    StateChange::Skip => self.with_host(&game_data, |game| {
+                        game.skip_question();
+                        game.services.audit.record(AuditEvent::by_session(&game.id, &game_data.session, AuditAction::QuestionSkipped)
+                            .detail(format!("question {}", game.question_index + 1)));
+                        ClientAction::None
+                    }),
                     StateChange::Disconnect => {
-                        if game_data.game_id.is_some() {
-                            let mut games = self.games.write().unwrap();
-                            let game_id = game_data.game_id.unwrap();
-                            let game = games.remove(&game_id);
-
-                            if game.is_some() {
-                                if game_data.hosting {
-                                    let mut game = game.unwrap();
-                                    info!("Shutting down game {} ({}) because host left", game.title, game.id);
-                                    game.broadcast(ServerPackets::Disconnect { reason: String::from("Game ended.") });
-                                } else if game_data.player_id.is_some() {
-                                    game.unwrap().remove_player(game_data.player_id.unwrap())
-                                }
-                            }
+                        if self.is_host(&game_data) {
+                            info!("Shutting down game {} ({}) because host left", self.title, self.id);
+                            self.services.audit.record(AuditEvent::by_session(&self.id, &game_data.session, AuditAction::GameEnded).detail("host left"));
+                            self.end("Game ended.");
+                            ctx.stop();
+                        } else if let Some(player_id) = game_data.player_id {
+                            self.remove_player(player_id)
                         }
                         ClientAction::Disconnect
                     }
                 }
             }
-            ServerAction::TryKick { id, game_data } => {
-                if !game_data.hosting {
-                    ClientAction::Error("You are not the host.")
-                } else {
-                    if game_data.game_id.is_some() {
-                        let mut games = self.games.write().unwrap();
-                        let game = games.get_mut(&game_data.game_id.unwrap());
-                        match game {
-                            None => ClientAction::Error("You are not in a game."),
-                            Some(game) => {
-                                game.remove_player(id);
-                                ClientAction::None
-                            }
-                        }
-                    } else {
-                        ClientAction::Error("You are not in a game.")
+            GameAction::TryKick { id, game_data } => self.with_host(&game_data, |game| {
+                match game.kick_player(id) {
+                    None => ClientAction::Error("That player is not in the game."),
+                    Some(player) => {
+                        info!("Banned player {} ({}) from game {}", player.name, player.id, game.id);
+                        game.services.audit.record(AuditEvent::by_session(&game.id, &game_data.session, AuditAction::PlayerKicked)
+                            .target(&player.id)
+                            .detail(player.name.clone()));
+                        game.services.audit.record(AuditEvent::by_session(&game.id, &game_data.session, AuditAction::PlayerBanned)
+                            .target(&player.id)
+                            .detail(session_hash(&player.session.id)));
+                        game.bans.push(Ban {
+                            id: player.id,
+                            name: player.name,
+                            session: player.session,
+                        });
+                        ClientAction::Packet(game.ban_list())
                     }
                 }
-            }
-            ServerAction::None => ClientAction::None,
+            }),
+            GameAction::TryUnban { id, game_data } => self.with_host(&game_data, |game| {
+                let count = game.bans.len();
+                game.bans.retain(|ban| ban.id != id);
+                if game.bans.len() == count {
+                    ClientAction::Error("That player is not banned.")
+                } else {
+                    game.services.audit.record(AuditEvent::by_session(&game.id, &game_data.session, AuditAction::PlayerUnbanned).target(&id));
+                    ClientAction::Packet(game.ban_list())
+                }
+            }),
+            GameAction::GetBans { game_data } => self.with_host(&game_data, |game| {
+                ClientAction::Packet(game.ban_list())
+            }),
+            GameAction::TryAnswer { answer, game_data } => match game_data.player_id {
+                Some(player_id) => self.answer(&player_id, answer),
+                None => ClientAction::Error("You are not in a game."),
+            },
+            GameAction::GetResults { format, game_data } => self.with_host(&game_data, |game| {
+                match &game.results {
+                    None => ClientAction::Error("The game hasn't finished yet."),
+                    Some(results) => ClientAction::Packet(ServerPackets::Results {
+                        data: results.export(&format),
+                        format,
+                    }),
+                }
+            }),
         })
     }
 }
@@ -268,18 +577,61 @@ impl Handler<ServerAction> for GameManager {
 #[derive(Debug)]
 pub struct Question {
     pub data: QuestionData,
+    /// The hash of the question image in the image store
+    pub image: Option<String>,
     pub start_time: Instant,
 }
 
+impl Question {
+    pub fn as_packet(&self, game_id: &str) -> ServerPackets {
+        ServerPackets::Question {
+            image: self.image.as_ref()
+                .map(|hash| image_path(game_id, hash))
+                .unwrap_or_default(),
+            question: self.data.question.clone(),
+            answers: self.data.values.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Game {
-    pub host: Addr<Connection>,
+    pub host: ClientAddr,
+    pub host_session: Identifier,
     pub id: Identifier,
     pub title: String,
     pub questions: Vec<Question>,
-    pub players: Arc<RwLock<HashMap<Identifier, Player>>>,
+    pub players: HashMap<Identifier, Player>,
+    pub bans: Vec<Ban>,
+    /// The results of players that left the game after answering questions
+    /// so that their answers are still in the results of the game
+    pub departed: Vec<PlayerResult>,
     pub state: GameState,
     pub timer: GameTimer,
+    pub question_index: usize,
+    /// Whether the current question has finished and is showing the scores
+    pub marking: bool,
+    /// The results of the game once it has finished
+    pub results: Option<GameResults>,
+    /// When the game was restored from a snapshot. Cleared once the time to
+    /// resume the game has passed.
+    pub restored: Option<Instant>,
+    pub services: GameServices,
+}
+
+impl Actor for Game {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.services.metrics.game_started(self.players.len());
+        ctx.run_interval(Game::TICK_INTERVAL, |game, ctx| game.tick(ctx));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.services.images.remove_game(&self.id);
+        self.services.metrics.game_stopped(self.players.len());
+        self.services.manager.do_send(GameStopped { id: self.id.clone() });
+    }
 }
 
 impl Game {
@@ -292,7 +644,7 @@ impl Game {
             let remaining = self.timer.remaining();
             let packet = ServerPackets::TimeSync {
                 total: VarInt(self.timer.duration.as_millis() as u32),
-                remaining: VarInt(remaining as u32),
+                remaining: VarInt(remaining),
             };
             self.broadcast(packet);
             if remaining == 0 {
@@ -302,13 +654,6 @@ impl Game {
     }
 }
 
-#[derive(Debug)]
-pub struct GameTime {
-    pub last_sync: Instant,
-    pub game_start: Instant,
-    pub need_sync: bool,
-}
-
 #[derive(Debug)]
 pub struct GameTimer {
     pub last_sync: Instant,
@@ -316,6 +661,14 @@ pub struct GameTimer {
     pub duration: Duration,
     pub elapsed: Duration,
     pub need_sync: bool,
+    /// When the timer was paused if it's paused
+    pub paused: Option<Instant>,
+}
+
+impl Default for GameTimer {
+    fn default() -> Self {
+        GameTimer::new()
+    }
 }
 
 impl GameTimer {
@@ -328,6 +681,7 @@ impl GameTimer {
             duration: Duration::from_secs(0),
             elapsed: Duration::from_secs(0),
             need_sync: false,
+            paused: None,
         }
     }
 
@@ -338,6 +692,29 @@ impl GameTimer {
         self.need_sync = true;
     }
 
+    pub fn is_finished(&self) -> bool {
+        self.paused.is_none() && self.start.elapsed() >= self.duration
+    }
+
+    /// The time left on the timer which doesn't go down while it's paused
+    pub fn time_left(&self) -> Duration {
+        let elapsed = self.paused.unwrap_or_else(Instant::now).saturating_duration_since(self.start);
+        self.duration.saturating_sub(elapsed)
+    }
+
+    /// Stops the timer until it's unpaused
+    pub fn pause(&mut self) {
+        self.paused.get_or_insert_with(Instant::now);
+    }
+
+    /// Carries the timer on from where it was paused returning how long it
+    /// was paused for
+    pub fn unpause(&mut self) -> Duration {
+        let paused = self.paused.take().map_or(Duration::ZERO, |time| time.elapsed());
+        self.start += paused;
+        paused
+    }
+
     pub fn remaining(&self) -> u32 {
         if self.duration < self.elapsed {
             0
@@ -348,34 +725,130 @@ impl GameTimer {
 }
 
 impl Game {
-    const ID_LENGTH: usize = 5;
+    pub(crate) const ID_LENGTH: usize = 5;
+    const TICK_INTERVAL: Duration = Duration::from_secs(1);
+    const START_DELAY: Duration = Duration::from_secs(5);
+    const QUESTION_TIME: Duration = Duration::from_secs(10);
+    const MARK_TIME: Duration = Duration::from_secs(3);
+    const BONUS_TIME: Duration = Duration::from_secs(5);
+    /// How long hosts and players have to resume a restored game
+    const RESUME_TIME: Duration = Duration::from_secs(60);
+
+    const POINTS: u32 = 100;
+    const BONUS_POINTS: f32 = 200.0;
 
-    fn is_name_taken(&self, name: &String) -> bool {
-        let players = self.players.read().unwrap();
-        players.values().any(|v| v.name.eq_ignore_ascii_case(name))
+    /// Moves the game along and keeps the timers of clients in sync
+    fn tick(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let start = Instant::now();
+        let _log = LogContext { game: Some(self.id.clone()), ..LogContext::default() }.enter();
+        // Restored games wait for their host before carrying on
+        if self.timer.paused.is_none() {
+            self.sync();
+            if let Some(results) = self.update() {
+                actix_web::rt::spawn(self.save_results(results));
+            }
+        }
+        if self.restored.is_some_and(|time| time.elapsed() >= Game::RESUME_TIME) {
+            self.restored = None;
+            if !self.expire_detached() {
+                info!("Ending restored game {} ({}) because the host didn't return", self.title, self.id);
+                self.services.audit.record(AuditEvent::new(&self.id, SERVER_ACTOR, AuditAction::GameEnded).detail("host didn't resume"));
+                self.end("Game ended.");
+                ctx.stop();
+            }
+        }
+        self.services.metrics.tick(start.elapsed());
+    }
+
+    /// Saves the results of this game off the game arbiter so that writing
+    /// them doesn't hold up the other games running on it
+    pub fn save_results(&self, results: GameResults) -> impl Future<Output=()> {
+        let store = self.services.results.clone();
+        async move {
+            let game = format!("{} ({})", results.title, results.game_id);
+            match web::block(move || store.save(&results)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("Failed to save the results of game {}: {}", game, err.reason()),
+                Err(err) => error!("Failed to save the results of game {}: {}", game, err),
+            }
+        }
+    }
+
+    /// Runs the provided function if the client is the host of this game.
+    /// Responds with an error if the client isn't the host.
+    fn with_host<F>(&mut self, game_data: &GameData, f: F) -> ClientAction
+        where F: FnOnce(&mut Game) -> ClientAction {
+        if self.is_host(game_data) {
+            f(self)
+        } else {
+            ClientAction::Error("You are not the host.")
+        }
+    }
+
+    /// Checks that the client is the host of this game. The hosting flag is
+    /// only trusted for the session that created the game.
+    fn is_host(&self, game_data: &GameData) -> bool {
+        game_data.hosting
+            && game_data.session == self.host_session
+            && game_data.game_id.as_ref() == Some(&self.id)
+    }
+
+    fn is_name_taken(&self, name: &str) -> bool {
+        let key = name_key(name);
+        self.players.values().any(|v| name_key(&v.name) == key)
+    }
+
+    /// Validates the provided name for use in this game returning the
+    /// cleaned up version of the name if it can be used
+    fn check_name(&self, name: &str) -> Result<String, NameError> {
+        let name = self.services.name_filter.validate(name)?;
+        if self.is_name_taken(&name) {
+            Err(NameError::Taken)
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn is_banned(&self, session: &Session) -> bool {
+        self.bans.iter().any(|ban| {
+            ban.session.id == session.id
+                || (self.services.ban_by_ip && ban.session.address.is_some() && ban.session.address == session.address)
+        })
+    }
+
+    fn ban_list(&self) -> ServerPackets {
+        ServerPackets::BanList {
+            bans: self.bans.iter().map(Ban::as_data).collect()
+        }
     }
 
     fn remove_player(&mut self, id: Identifier) {
-        let mut players = self.players.write().unwrap();
-        let player = players.remove(&id);
-        match player {
-            None => {}
-            Some(player) => {
-                players.values().for_each(|p| p.ret.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Remove))));
-                player.ret.do_send(ClientAction::Multiple(vec![
-                    ClientAction::Packet(ServerPackets::Disconnect { reason: String::from("Removed from game.") }),
-                    ClientAction::Disconnect,
-                ]))
+        self.kick_player(id);
+    }
+
+    pub(crate) fn kick_player(&mut self, id: Identifier) -> Option<Player> {
+        let player = self.players.remove(&id);
+        if let Some(player) = &player {
+            if !player.answers.is_empty() {
+                self.departed.push(player.result(self.questions.len()));
             }
+            self.services.metrics.player_left();
+            self.players.values().for_each(|p| p.ret.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Remove))));
+            self.host.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Remove)));
+            player.ret.do_send(ClientAction::Multiple(vec![
+                ClientAction::Packet(ServerPackets::Disconnect { reason: String::from("Removed from game.") }),
+                ClientAction::Disconnect,
+            ]))
         }
+        player
     }
 
-    fn new_player(&mut self, name: String, ret: Addr<Connection>) -> Identifier {
-        let mut players = self.players.write().unwrap();
+    fn new_player(&mut self, name: String, ret: Addr<Connection>, session: Session) -> Identifier {
         let mut id: Identifier;
         loop {
             id = random_identifier(Player::ID_LENGTH);
-            if !players.contains_key(&id) { break; };
+            // Ids of departed players are avoided so their results stay separate
+            if !self.players.contains_key(&id) && !self.departed.iter().any(|player| player.id == id) { break; };
         };
         let player = Player {
             id: id.clone(),
@@ -383,40 +856,245 @@ impl Game {
             score: 0,
             answers: HashMap::new(),
             answer_time: None,
-            ret: ret.clone(),
+            ret: ClientAddr::from(ret.clone()),
+            session,
         };
-        for v in players.values() {
+        for v in self.players.values() {
             v.ret.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Add)));
             ret.do_send(ClientAction::Packet(v.as_data(PlayerDataMode::Add)));
         }
         ret.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Me)));
         self.host.do_send(ClientAction::Packet(player.as_data(PlayerDataMode::Add)));
-        players.insert(id.clone(), player);
+        self.players.insert(id.clone(), player);
+        self.services.metrics.player_joined();
         id
     }
 
+    /// Reattaches the host or player with the provided session to this game
+    /// after it was restored from a snapshot. Responds with everything the
+    /// client needs to carry on from where it was.
+    fn resume(&mut self, ctx: &<Self as Actor>::Context, ret: Addr<Connection>, session: &Session) -> Option<ClientAction> {
+        let player_id = if self.host_session == session.id && self.host.is_detached() {
+            self.host = ClientAddr::from(ret);
+            let paused = self.timer.unpause();
+            if let Some(question) = self.questions.get_mut(self.question_index) {
+                question.start_time += paused;
+            }
+            None
+        } else {
+            let player = self.players.values_mut()
+                .find(|player| player.session.id == session.id && player.ret.is_detached())?;
+            player.ret = ClientAddr::from(ret);
+            player.session = session.clone();
+            Some(player.id.clone())
+        };
+        match &player_id {
+            Some(player_id) => info!("Player {} resumed game {} ({})", player_id, self.title, self.id),
+            None => info!("Host resumed game {} ({})", self.title, self.id),
+        }
+
+        let mut actions = vec![
+            ClientAction::ResumedGame { id: self.id.clone(), player_id: player_id.clone(), title: self.title.clone(), game: ctx.address() },
+            ClientAction::Packet(ServerPackets::GameState { state: self.state.clone() }),
+        ];
+        for player in self.players.values() {
+            let mode = if player_id.as_ref() == Some(&player.id) { PlayerDataMode::Me } else { PlayerDataMode::Add };
+            actions.push(ClientAction::Packet(player.as_data(mode)));
+        }
+        let scores = self.players.values()
+            .map(|player| (player.id.clone(), player.score))
+            .collect();
+        actions.push(ClientAction::Packet(ServerPackets::Scores { scores }));
+        if self.state == GameState::Started && !self.marking {
+            actions.push(ClientAction::Packet(self.questions[self.question_index].as_packet(&self.id)));
+        }
+        if self.timer.need_sync {
+            actions.push(ClientAction::Packet(ServerPackets::TimeSync {
+                total: VarInt(self.timer.duration.as_millis() as u32),
+                remaining: VarInt(self.timer.time_left().as_millis() as u32),
+            }));
+        }
+        Some(ClientAction::Multiple(actions))
+    }
+
+    /// Removes the players that didn't resume this game after it was restored.
+    /// Returns whether the host resumed the game.
+    fn expire_detached(&mut self) -> bool {
+        let detached: Vec<Identifier> = self.players.values()
+            .filter(|player| player.ret.is_detached())
+            .map(|player| player.id.clone())
+            .collect();
+        for id in detached {
+            self.kick_player(id);
+        }
+        !self.host.is_detached()
+    }
+
+    /// Moves the game along once the current timer has finished. Returns the
+    /// results of the game when the last question has finished.
+    fn update(&mut self) -> Option<GameResults> {
+        match self.state {
+            GameState::Starting if self.timer.is_finished() => {
+                if self.questions.is_empty() {
+                    return Some(self.stop());
+                }
+                self.state = GameState::Started;
+                self.broadcast(ServerPackets::GameState { state: GameState::Started });
+                self.ask_question(0);
+            }
+            GameState::Started if !self.marking && (self.timer.is_finished() || self.all_answered()) => {
+                self.mark_question();
+            }
+            GameState::Started if self.marking && self.timer.is_finished() => {
+                if self.question_index + 1 < self.questions.len() {
+                    self.ask_question(self.question_index + 1);
+                } else {
+                    return Some(self.stop());
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn ask_question(&mut self, index: usize) {
+        self.question_index = index;
+        self.marking = false;
+        let question = &mut self.questions[index];
+        question.start_time = Instant::now();
+        let packet = question.as_packet(&self.id);
+        self.broadcast(packet);
+        self.timer.track(Game::QUESTION_TIME);
+    }
+
+    /// Ends the current question and shows everyone the scores
+    fn mark_question(&mut self) {
+        self.marking = true;
+        let scores = self.players.values()
+            .map(|player| (player.id.clone(), player.score))
+            .collect();
+        self.broadcast(ServerPackets::Scores { scores });
+        self.timer.track(Game::MARK_TIME);
+    }
+
+    fn skip_question(&mut self) {
+        if self.state == GameState::Started && !self.marking {
+            self.mark_question();
+        }
+    }
+
+    fn all_answered(&self) -> bool {
+        !self.players.is_empty() && self.players.values().all(|player| player.answers.contains_key(&self.question_index))
+    }
+
+    fn answer(&mut self, player_id: &Identifier, answer: AnswerIndex) -> ClientAction {
+        if self.state != GameState::Started || self.marking || self.timer.paused.is_some() {
+            return ClientAction::Error("You can't answer right now.");
+        }
+        let index = self.question_index;
+        if usize::from(answer) >= self.questions[index].data.values.len() {
+            return ClientAction::Error("That answer doesn't exist.");
+        }
+        let Some(player) = self.players.get_mut(player_id) else {
+            return ClientAction::Error("You are not in a game.");
+        };
+        if player.answers.contains_key(&index) {
+            return ClientAction::Error("You have already answered this question.");
+        }
+        let question = &self.questions[self.question_index];
+        let time = question.start_time.elapsed();
+        let correct = question.data.answers.contains(&answer);
+        let points = if correct {
+            // Faster answers get a bonus which shrinks to nothing at the bonus time
+            let bonus = 1.0 - (time.as_secs_f32() / Game::BONUS_TIME.as_secs_f32()).min(1.0);
+            Game::POINTS + (Game::BONUS_POINTS * bonus) as u32
+        } else {
+            0
+        };
+        player.score += points;
+        player.answer_time = Some(Instant::now());
+        player.answers.insert(index, AnswerRecord {
+            answer,
+            correct,
+            time: time.as_millis() as u32,
+            points,
+        });
+        ClientAction::Packet(ServerPackets::AnswerResult { result: correct })
+    }
+
+    /// Finishes the game creating the results for each of the players
+    pub(crate) fn stop(&mut self) -> GameResults {
+        self.state = GameState::Stopped;
+        self.timer.need_sync = false;
+        self.broadcast(ServerPackets::GameState { state: GameState::Stopped });
+        let mut players: Vec<PlayerResult> = self.players.values()
+            .map(|player| player.result(self.questions.len()))
+            .chain(self.departed.iter().cloned())
+            .collect();
+        players.sort_by_key(|player| std::cmp::Reverse(player.score));
+        let results = GameResults {
+            game_id: self.id.clone(),
+            host_session: self.host_session.clone(),
+            title: self.title.clone(),
+            finished_at: GameResults::now(),
+            questions: self.questions.iter().map(|question| question.data.question.clone()).collect(),
+            players,
+        };
+        info!("Game {} ({}) finished", self.title, self.id);
+        self.results = Some(results.clone());
+        results
+    }
+
+    /// Disconnects everyone in the game with the provided reason
+    pub(crate) fn end(&self, reason: &str) {
+        let action = ClientAction::Multiple(vec![
+            ClientAction::Packet(ServerPackets::Disconnect { reason: String::from(reason) }),
+            ClientAction::Disconnect,
+        ]);
+        self.players.values().for_each(|p| p.ret.do_send(action.clone()));
+        self.host.do_send(action)
+    }
+
     fn broadcast(&self, packet: ServerPackets) {
         let action = ClientAction::Packet(packet);
-        let players = self.players.read().unwrap();
-        players.values().for_each(|p| p.ret.do_send(action.clone()));
+        self.players.values().for_each(|p| p.ret.do_send(action.clone()));
         self.host.do_send(action)
     }
+}
+
+
+/// The connection of a host or player. This is empty when a game has been
+/// restored from a snapshot until they resume the game.
+#[derive(Debug, Clone, Default)]
+pub struct ClientAddr(Option<Addr<Connection>>);
 
-    fn broadcast_excluding(&self, excluding: Identifier, packet: ServerPackets) {
-        let players = self.players.read().unwrap();
-        players.values().filter(|p| p.id != excluding).for_each(|p| p.ret.do_send(ClientAction::Packet(packet.clone())))
+impl ClientAddr {
+    pub fn do_send(&self, action: ClientAction) {
+        if let Some(addr) = &self.0 {
+            addr.do_send(action);
+        }
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.0.is_none()
     }
 }
 
+impl From<Addr<Connection>> for ClientAddr {
+    fn from(addr: Addr<Connection>) -> Self {
+        ClientAddr(Some(addr))
+    }
+}
 
 #[derive(Debug)]
 pub struct Player {
     pub id: Identifier,
     pub name: String,
     pub score: u32,
-    pub answers: HashMap<QuestionIndex, AnswerIndex>,
+    pub answers: HashMap<QuestionIndex, AnswerRecord>,
     pub answer_time: Option<Instant>,
-    pub ret: Addr<Connection>,
+    pub ret: ClientAddr,
+    pub session: Session,
 }
 
 impl Player {
@@ -429,4 +1107,34 @@ impl Player {
             mode,
         }
     }
-}
\ No newline at end of file
+
+    /// Creates the results of this player for a game with the provided
+    /// number of questions
+    pub fn result(&self, questions: usize) -> PlayerResult {
+        PlayerResult {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            score: self.score,
+            answers: (0..questions)
+                .map(|index| self.answers.get(&index).cloned())
+                .collect(),
+        }
+    }
+}
+
+/// A player that has been kicked from a game and is no longer allowed to join
+#[derive(Debug, Clone)]
+pub struct Ban {
+    pub id: Identifier,
+    pub name: String,
+    pub session: Session,
+}
+
+impl Ban {
+    pub fn as_data(&self) -> BanData {
+        BanData {
+            id: self.id.clone(),
+            name: self.name.clone(),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+use actix::{Addr, Handler, MailboxError, Message, MessageResult};
+use actix_web::{HttpResponse, web::Data};
+use tracing::warn;
+use serde_json::{json, Value};
+use crate::game::GameManager;
+use crate::tools::env_value;
+
+/// Message used to check that the game manager is processing its mailbox
+#[derive(Message)]
+#[rtype(result = "ManagerStatus")]
+pub struct Ping;
+
+pub struct ManagerStatus {
+    pub games: usize,
+    pub players: usize,
+    pub shutting_down: bool,
+}
+
+impl Handler<Ping> for GameManager {
+    type Result = MessageResult<Ping>;
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(ManagerStatus {
+            games: self.games.len(),
+            players: self.metrics.players(),
+            shutting_down: self.shutting_down,
+        })
+    }
+}
+
+/// How long the game manager has to respond before it's considered wedged
+#[derive(Debug, Clone, Copy)]
+pub struct HealthDeadline(Duration);
+
+impl HealthDeadline {
+    pub fn from_env() -> HealthDeadline {
+        HealthDeadline(Duration::from_millis(env_value("QUIZLER_HEALTH_TIMEOUT", 2000)))
+    }
+}
+
+/// Round trips a message through the game manager mailbox creating the
+/// response for the health check. Details are only included for readiness
+/// checks which also fail while the server is shutting down.
+async fn check(manager: &Addr<GameManager>, deadline: HealthDeadline, details: bool) -> HttpResponse {
+    let start = Instant::now();
+    let result = manager.send(Ping).timeout(deadline.0).await;
+    let latency = start.elapsed().as_secs_f64() * 1000.0;
+    match result {
+        Ok(status) => {
+            let mut value = json!({
+                "status": "ok",
+                "manager": "responsive",
+                "latency_ms": latency,
+            });
+            if !details {
+                return HttpResponse::Ok().json(value);
+            }
+            value["games"] = Value::from(status.games);
+            value["players"] = Value::from(status.players);
+            if status.shutting_down {
+                value["status"] = Value::from("shutting_down");
+                HttpResponse::ServiceUnavailable().json(value)
+            } else {
+                HttpResponse::Ok().json(value)
+            }
+        }
+        Err(err) => {
+            let manager = match err {
+                MailboxError::Timeout => "unresponsive",
+                MailboxError::Closed => "stopped",
+            };
+            warn!("Health check failed, the game manager is {}", manager);
+            HttpResponse::ServiceUnavailable().json(json!({
+                "status": "unavailable",
+                "manager": manager,
+                "latency_ms": latency,
+                "deadline_ms": deadline.0.as_millis() as u64,
+            }))
+        }
+    }
+}
+
+/// Route handler for the liveness check
+pub async fn healthz(manager: Data<Addr<GameManager>>, deadline: Data<HealthDeadline>) -> HttpResponse {
+    check(&manager, *deadline.get_ref(), false).await
+}
+
+/// Route handler for the readiness check which also reports the number of
+/// games and players the server is handling
+pub async fn readyz(manager: Data<Addr<GameManager>>, deadline: Data<HealthDeadline>) -> HttpResponse {
+    check(&manager, *deadline.get_ref(), true).await
+}
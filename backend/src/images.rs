@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
+use actix_web::{HttpResponse, web::{Bytes, Data, Path}};
+use actix_web::http::header::{CacheControl, CacheDirective, ContentType, ETag, EntityTag};
+use image::{AnimationDecoder, DynamicImage, Frame, ImageDecoder, ImageFormat, ImageReader, Limits};
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::imageops::FilterType as ResizeFilter;
+use tracing::info;
+use sha2::{Digest, Sha256};
+use crate::packets::{Blob, QuestionData};
+use crate::tools::{env_value, Identifier};
+
+/// The image formats that are allowed to be used for question images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageType {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+}
+
+impl ImageType {
+    /// Determines the type of image from the magic bytes at the start of it
+    pub fn sniff(data: &[u8]) -> Option<ImageType> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageType::Png)
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageType::Jpeg)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(ImageType::Gif)
+        } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+            Some(ImageType::WebP)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_mime(mime: &str) -> Option<ImageType> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            "image/png" => Some(ImageType::Png),
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(ImageType::Jpeg),
+            "image/gif" => Some(ImageType::Gif),
+            "image/webp" => Some(ImageType::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageType::Png => "image/png",
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Gif => "image/gif",
+            ImageType::WebP => "image/webp",
+        }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        match self {
+            ImageType::Png => ImageFormat::Png,
+            ImageType::Jpeg => ImageFormat::Jpeg,
+            ImageType::Gif => ImageFormat::Gif,
+            ImageType::WebP => ImageFormat::WebP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    UnsupportedType,
+    TypeMismatch,
+    TooLarge,
+    TooManyPixels,
+    TooManyFrames,
+    Invalid,
+}
+
+impl ImageError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ImageError::UnsupportedType => "Question images must be PNG, JPEG, GIF or WebP images",
+            ImageError::TypeMismatch => "A question image doesn't match its declared image type",
+            ImageError::TooLarge => "A question image is too large",
+            ImageError::TooManyPixels => "A question image has dimensions that are too large",
+            ImageError::TooManyFrames => "A question image has too many animation frames",
+            ImageError::Invalid => "A question image is invalid or corrupted",
+        }
+    }
+}
+
+/// Limits that uploaded images are checked against
+#[derive(Debug, Clone, Copy)]
+pub struct ImageLimits {
+    /// The maximum size of the encoded image in bytes
+    pub max_size: usize,
+    /// The maximum width or height of the image
+    pub max_dimension: u32,
+    /// The maximum number of pixels once the image is decoded
+    pub max_pixels: u64,
+    /// Images larger than this in either dimension are downscaled to fit
+    pub target_dimension: u32,
+    /// The quality used when re-encoding images as JPEG
+    pub jpeg_quality: u8,
+    /// The maximum number of frames in a GIF that needs downscaling
+    pub max_frames: usize,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        ImageLimits {
+            max_size: 16 * 1024 * 1024,
+            max_dimension: 8192,
+            max_pixels: 50_000_000,
+            target_dimension: 1600,
+            jpeg_quality: 85,
+            max_frames: 300,
+        }
+    }
+}
+
+impl ImageLimits {
+    pub fn from_env() -> ImageLimits {
+        let default = ImageLimits::default();
+        ImageLimits {
+            max_size: env_value("QUIZLER_MAX_IMAGE_SIZE", default.max_size),
+            max_dimension: env_value("QUIZLER_MAX_IMAGE_DIMENSION", default.max_dimension),
+            max_pixels: default.max_pixels,
+            target_dimension: env_value("QUIZLER_IMAGE_DIMENSION", default.target_dimension),
+            jpeg_quality: env_value("QUIZLER_IMAGE_QUALITY", default.jpeg_quality).clamp(1, 100),
+            max_frames: default.max_frames,
+        }
+    }
+
+    /// Checks the provided image against the declared type and these limits
+    /// returning the actual type of the image.
+    pub fn validate(&self, declared_type: &str, data: &[u8]) -> Result<ImageType, ImageError> {
+        if data.len() > self.max_size {
+            return Err(ImageError::TooLarge);
+        }
+        let image_type = ImageType::sniff(data).ok_or(ImageError::UnsupportedType)?;
+        let declared = ImageType::from_mime(declared_type).ok_or(ImageError::UnsupportedType)?;
+        if declared != image_type {
+            return Err(ImageError::TypeMismatch);
+        }
+        let (width, height) = ImageReader::with_format(Cursor::new(data), image_type.format())
+            .into_dimensions()
+            .map_err(|_| ImageError::Invalid)?;
+        if width == 0 || height == 0 {
+            return Err(ImageError::Invalid);
+        }
+        if width > self.max_dimension || height > self.max_dimension
+            || (width as u64) * (height as u64) > self.max_pixels {
+            return Err(ImageError::TooManyPixels);
+        }
+        Ok(image_type)
+    }
+
+    /// The limits given to image decoders. Decoded images can be hundreds of
+    /// megabytes so decoders may only allocate enough for an RGBA image with
+    /// the maximum number of pixels.
+    fn decoder_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(self.max_dimension);
+        limits.max_image_height = Some(self.max_dimension);
+        limits.max_alloc = Some(self.max_pixels * 4);
+        limits
+    }
+
+    /// Processes the images of all the provided questions. Images are
+    /// processed one at a time so that only one decoded image is held in
+    /// memory for each request.
+    pub fn process_all(&self, questions: &mut [QuestionData]) -> Result<(), ImageError> {
+        for question in questions.iter_mut().filter(|question| !question.image.is_empty()) {
+            let data = std::mem::take(&mut question.image.0);
+            let (image_type, data) = self.process(&question.image_type, data)?;
+            question.image_type = String::from(image_type.mime());
+            question.image = Blob(data);
+        }
+        Ok(())
+    }
+
+    /// Validates the provided image and then decodes it, downscaling it to fit
+    /// the target dimension and re-encoding it which also strips any metadata
+    /// such as the EXIF location. Opaque images become JPEGs and images with
+    /// transparency become PNGs, GIFs stay GIFs to keep their animations.
+    pub fn process(&self, declared_type: &str, data: Vec<u8>) -> Result<(ImageType, Vec<u8>), ImageError> {
+        let image_type = self.validate(declared_type, &data)?;
+        if image_type == ImageType::Gif {
+            return self.process_gif(data).map(|data| (image_type, data));
+        }
+        let mut reader = ImageReader::with_format(Cursor::new(&data), image_type.format());
+        reader.limits(self.decoder_limits());
+        let mut decoder = reader.into_decoder().map_err(|_| ImageError::Invalid)?;
+        let orientation = decoder.orientation().map_err(|_| ImageError::Invalid)?;
+        let mut image = DynamicImage::from_decoder(decoder).map_err(|_| ImageError::Invalid)?;
+        image.apply_orientation(orientation);
+        if image.width() > self.target_dimension || image.height() > self.target_dimension {
+            image = image.resize(self.target_dimension, self.target_dimension, ResizeFilter::Lanczos3);
+        }
+
+        let mut output = Vec::new();
+        let output_type = if image.color().has_alpha() {
+            image.write_with_encoder(PngEncoder::new_with_quality(&mut output, CompressionType::Best, FilterType::Adaptive))
+                .map(|_| ImageType::Png)
+        } else {
+            image.into_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut output, self.jpeg_quality))
+                .map(|_| ImageType::Jpeg)
+        }.map_err(|_| ImageError::Invalid)?;
+        info!(
+            "Re-encoded {} image ({} bytes) as {} ({} bytes, saved {} bytes)",
+            image_type.mime(), data.len(), output_type.mime(), output.len(),
+            data.len() as i64 - output.len() as i64
+        );
+        Ok((output_type, output))
+    }
+
+    /// Downscales every frame of a GIF larger than the target dimension. GIFs
+    /// that already fit are kept as they are.
+    fn process_gif(&self, data: Vec<u8>) -> Result<Vec<u8>, ImageError> {
+        let mut decoder = GifDecoder::new(Cursor::new(&data)).map_err(|_| ImageError::Invalid)?;
+        decoder.set_limits(self.decoder_limits()).map_err(|_| ImageError::TooManyPixels)?;
+        let (width, height) = decoder.dimensions();
+        if width <= self.target_dimension && height <= self.target_dimension {
+            return Ok(data);
+        }
+        let mut output = Vec::new();
+        let mut encoder = GifEncoder::new_with_speed(&mut output, 10);
+        encoder.set_repeat(Repeat::Infinite).map_err(|_| ImageError::Invalid)?;
+        for (index, frame) in decoder.into_frames().enumerate() {
+            if index >= self.max_frames {
+                return Err(ImageError::TooManyFrames);
+            }
+            let frame = frame.map_err(|_| ImageError::Invalid)?;
+            let delay = frame.delay();
+            let buffer = DynamicImage::ImageRgba8(frame.into_buffer())
+                .resize(self.target_dimension, self.target_dimension, ResizeFilter::Triangle)
+                .into_rgba8();
+            encoder.encode_frame(Frame::from_parts(buffer, 0, 0, delay)).map_err(|_| ImageError::Invalid)?;
+        }
+        drop(encoder);
+        info!("Downscaled GIF image ({} bytes) to {} bytes", data.len(), output.len());
+        Ok(output)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredImage {
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+/// Content addressed store for the question images of each game. Images are
+/// served over HTTP so that clients can cache them instead of having them
+/// sent inside every question packet.
+#[derive(Debug, Clone, Default)]
+pub struct ImageStore {
+    games: Arc<RwLock<HashMap<Identifier, HashMap<String, StoredImage>>>>,
+}
+
+pub fn hash_image(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    let mut out = String::with_capacity(digest.len() * 2);
+    for byte in digest {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Creates the path that the image with the provided hash is served from
+pub fn image_path(game: &str, hash: &str) -> String {
+    format!("/images/{}/{}", game, hash)
+}
+
+impl ImageStore {
+    /// Stores the provided image for the game returning the hash it is stored
+    /// under. Identical images are only stored once.
+    pub fn insert(&self, game: &Identifier, content_type: String, data: Vec<u8>) -> String {
+        let hash = hash_image(&data);
+        let mut games = self.games.write().unwrap();
+        games.entry(game.clone())
+            .or_default()
+            .entry(hash.clone())
+            .or_insert_with(|| StoredImage {
+                content_type,
+                data: Bytes::from(data),
+            });
+        hash
+    }
+
+    pub fn get(&self, game: &str, hash: &str) -> Option<StoredImage> {
+        let games = self.games.read().unwrap();
+        games.get(game)?.get(hash).cloned()
+    }
+
+    pub fn remove_game(&self, game: &str) {
+        let mut games = self.games.write().unwrap();
+        games.remove(game);
+    }
+}
+
+/// Route handler serving the images stored for games. Because images are
+/// addressed by their hash they never change so can be cached forever.
+pub async fn serve_image(path: Path<(String, String)>, images: Data<ImageStore>) -> HttpResponse {
+    let (game, hash) = path.into_inner();
+    match images.get(&game, &hash) {
+        None => HttpResponse::NotFound().finish(),
+        Some(image) => {
+            let content_type = ImageType::from_mime(&image.content_type)
+                .and_then(|image_type| image_type.mime().parse().ok())
+                .unwrap_or(ContentType::octet_stream().0);
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header(CacheControl(vec![
+                    CacheDirective::Public,
+                    CacheDirective::MaxAge(31536000),
+                    CacheDirective::Extension(String::from("immutable"), None),
+                ]))
+                .insert_header(ETag(EntityTag::new_strong(hash)))
+                .insert_header(("X-Content-Type-Options", "nosniff"))
+                .body(image.data)
+        }
+    }
+}
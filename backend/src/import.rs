@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use actix_web::{HttpRequest, HttpResponse, web::{self, Bytes, Data, Path, Query}};
+use serde_json::json;
+use crate::library::{Quiz, QuizLibrary};
+use crate::limits::AddressLimiter;
+use crate::packets::{Blob, QuestionData};
+use crate::tools::client_address;
+
+/// The most answers a question can have, this matches MAX_ANSWERS in the
+/// frontend constants.ts
+pub const MAX_ANSWERS: usize = 9;
+
+/// The formats that question banks can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Gift,
+}
+
+impl ImportFormat {
+    pub fn from_name(name: &str) -> Option<ImportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(ImportFormat::Csv),
+            "gift" | "txt" => Some(ImportFormat::Gift),
+            _ => None,
+        }
+    }
+
+    /// Determines the format of a file from its extension
+    pub fn from_path(path: &str) -> Option<ImportFormat> {
+        let (_, extension) = path.rsplit_once('.')?;
+        ImportFormat::from_name(extension)
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Imported, Vec<ImportError>> {
+        match self {
+            ImportFormat::Csv => parse_csv(text),
+            ImportFormat::Gift => parse_gift(text).map(|questions| Imported { questions, warnings: Vec::new() }),
+        }
+    }
+}
+
+/// The questions read from an imported file along with warnings about any
+/// parts of the file that were ignored
+#[derive(Debug, Clone)]
+pub struct Imported {
+    pub questions: Vec<QuestionData>,
+    pub warnings: Vec<ImportError>,
+}
+
+/// An error or warning in an imported file along with the line it occurred on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ImportError {
+    fn new<M: Into<String>>(line: usize, message: M) -> ImportError {
+        ImportError { line, message: message.into() }
+    }
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Creates a question checking that it has a sensible number of answers and
+/// that the correct answers are all in range.
+fn question(line: usize, question: String, values: Vec<String>, answers: Vec<u8>) -> Result<QuestionData, ImportError> {
+    if question.is_empty() {
+        return Err(ImportError::new(line, "Question text is empty"));
+    }
+    if values.len() < 2 {
+        return Err(ImportError::new(line, "Questions need at least 2 answers"));
+    }
+    if values.len() > MAX_ANSWERS {
+        return Err(ImportError::new(line, format!("Questions can't have more than {} answers", MAX_ANSWERS)));
+    }
+    if values.iter().any(String::is_empty) {
+        return Err(ImportError::new(line, "Answers can't be empty"));
+    }
+    if answers.is_empty() {
+        return Err(ImportError::new(line, "Questions need at least 1 correct answer"));
+    }
+    Ok(QuestionData {
+        image_type: String::new(),
+        image: Blob::default(),
+        question,
+        values,
+        answers,
+    })
+}
+
+/// Splits CSV text into records of fields along with the line each record
+/// started on. Quoted fields may contain delimiters, new lines and escaped
+/// quotes.
+fn csv_records(text: &str, delimiter: char) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(ImportError::new(start, "Quoted field is never closed"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// Picks the delimiter used by a CSV file from the first line because
+/// spreadsheets in some locales export using semicolons or tabs.
+fn csv_delimiter(text: &str) -> char {
+    let first = text.lines().next().unwrap_or_default();
+    [',', ';', '\t']
+        .into_iter()
+        .max_by_key(|delimiter| first.matches(*delimiter).count())
+        .unwrap_or(',')
+}
+
+/// The columns of a CSV question bank
+struct CsvColumns {
+    question: usize,
+    options: Vec<usize>,
+    correct: usize,
+    time_limit: Option<usize>,
+}
+
+impl CsvColumns {
+    /// Reads the column layout from a header row returning None if the row
+    /// isn't a header.
+    fn from_header(header: &[String]) -> Option<CsvColumns> {
+        let names: Vec<String> = header.iter()
+            .map(|name| name.trim().to_ascii_lowercase().replace(['_', ' '], ""))
+            .collect();
+        let question = names.iter().position(|name| name == "question")?;
+        let correct = names.iter().position(|name| name == "correct" || name == "answer")?;
+        let time_limit = names.iter().position(|name| name == "timelimit" || name == "time");
+        let options = names.iter()
+            .enumerate()
+            .filter(|(_, name)| name.starts_with("option") || (name.starts_with("answer") && name.len() > 6))
+            .map(|(index, _)| index)
+            .collect();
+        Some(CsvColumns { question, options, correct, time_limit })
+    }
+
+    /// The layout used for files without a header. The question comes first
+    /// followed by the options with the correct answers and time limit last.
+    fn positional(width: usize) -> CsvColumns {
+        CsvColumns {
+            question: 0,
+            options: (1..width.saturating_sub(2)).collect(),
+            correct: width.saturating_sub(2),
+            time_limit: Some(width.saturating_sub(1)),
+        }
+    }
+}
+
+fn field(record: &[String], index: usize) -> &str {
+    record.get(index).map(|value| value.trim()).unwrap_or_default()
+}
+
+/// Parses the correct answers column which may contain 1 based answer
+/// numbers, answer letters or the answer text separated by ; or |
+fn parse_correct(line: usize, value: &str, options: &[String]) -> Result<Vec<u8>, ImportError> {
+    let mut correct = Vec::new();
+    for token in value.split([';', '|', ',']).map(str::trim).filter(|token| !token.is_empty()) {
+        let index = if let Ok(number) = token.parse::<usize>() {
+            number.checked_sub(1)
+        } else if token.len() == 1 && token.chars().all(|c| c.is_ascii_alphabetic()) {
+            Some((token.to_ascii_uppercase().as_bytes()[0] - b'A') as usize)
+        } else {
+            options.iter().position(|option| option.eq_ignore_ascii_case(token))
+        };
+        match index {
+            Some(index) if index < options.len() => {
+                if !correct.contains(&(index as u8)) {
+                    correct.push(index as u8);
+                }
+            }
+            _ => return Err(ImportError::new(line, format!("Correct answer \"{}\" doesn't match any of the options", token))),
+        }
+    }
+    Ok(correct)
+}
+
+/// Parses a CSV question bank with columns for the question, the options, the
+/// correct answers and an optional time limit in seconds. Time limits are
+/// checked but not used because every question shares the same time limit so
+/// a warning is given for the first question that sets one.
+pub fn parse_csv(text: &str) -> Result<Imported, Vec<ImportError>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let records = csv_records(text, csv_delimiter(text)).map_err(|err| vec![err])?;
+    let mut records = records.into_iter()
+        .filter(|(_, record)| record.iter().any(|value| !value.trim().is_empty()))
+        .peekable();
+    let columns = match records.peek() {
+        None => return Err(vec![ImportError::new(1, "The file doesn't contain any questions")]),
+        Some((_, first)) => match CsvColumns::from_header(first) {
+            Some(columns) => {
+                records.next();
+                columns
+            }
+            None => CsvColumns::positional(first.len()),
+        },
+    };
+
+    let mut questions = Vec::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for (line, record) in records {
+        let options: Vec<String> = columns.options.iter()
+            .map(|index| String::from(field(&record, *index)))
+            .filter(|option| !option.is_empty())
+            .collect();
+        let result = parse_correct(line, field(&record, columns.correct), &options)
+            .and_then(|correct| {
+                if let Some(time_limit) = columns.time_limit.map(|index| field(&record, index)) {
+                    if !time_limit.is_empty() && !time_limit.parse::<u32>().is_ok_and(|value| value > 0) {
+                        return Err(ImportError::new(line, format!("Time limit \"{}\" isn't a positive number of seconds", time_limit)));
+                    }
+                    if !time_limit.is_empty() && warnings.is_empty() {
+                        warnings.push(ImportError::new(line, "Time limits are ignored because every question has the same time limit"));
+                    }
+                }
+                question(line, String::from(field(&record, columns.question)), options, correct)
+            });
+        match result {
+            Ok(question) => questions.push(question),
+            Err(err) => errors.push(err),
+        }
+    }
+    if errors.is_empty() {
+        Ok(Imported { questions, warnings })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Removes the GIFT escape characters from the provided text
+fn gift_unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                out.push(if next == 'n' { '\n' } else { next });
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Splits the provided text at each unescaped occurrence of any of the
+/// provided characters keeping the character that each part started with.
+fn gift_split<'a>(text: &'a str, at: &[char]) -> Vec<(Option<char>, &'a str)> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut marker = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if at.contains(&c) {
+            parts.push((marker, &text[start..index]));
+            marker = Some(c);
+            start = index + c.len_utf8();
+        }
+    }
+    parts.push((marker, &text[start..]));
+    parts
+}
+
+/// Finds the first unescaped occurrence of the provided character
+fn gift_find(text: &str, target: char) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == target {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Parses a single GIFT question returning None for blocks such as category
+/// declarations which don't contain a question.
+fn gift_question(line: usize, block: &str) -> Option<Result<QuestionData, ImportError>> {
+    let mut block = block.trim();
+    if block.starts_with("$CATEGORY:") {
+        return None;
+    }
+    // Question titles are written as ::Title:: before the question
+    if let Some(rest) = block.strip_prefix("::") {
+        match rest.find("::") {
+            Some(end) => block = rest[end + 2..].trim_start(),
+            None => return Some(Err(ImportError::new(line, "Question title is never closed"))),
+        }
+    }
+    let block = ["[html]", "[moodle]", "[plain]", "[markdown]"]
+        .iter()
+        .find_map(|format| block.strip_prefix(format))
+        .unwrap_or(block);
+    let Some(open) = gift_find(block, '{') else {
+        return Some(Err(ImportError::new(line, "Question is missing its {answers}")));
+    };
+    let Some(close) = gift_find(&block[open..], '}').map(|close| open + close) else {
+        return Some(Err(ImportError::new(line, "Question answers are never closed with }")));
+    };
+    let mut text = String::from(&block[..open]);
+    text.push_str(&block[close + 1..]);
+    let text = gift_unescape(&text.replace('\n', " "));
+    let body = block[open + 1..close].trim();
+
+    let result = match body.to_ascii_uppercase().as_str() {
+        "T" | "TRUE" => question(line, text, vec![String::from("True"), String::from("False")], vec![0]),
+        "F" | "FALSE" => question(line, text, vec![String::from("True"), String::from("False")], vec![1]),
+        _ if body.starts_with('#') => Err(ImportError::new(line, "Numerical GIFT questions aren't supported")),
+        _ => {
+            let mut values = Vec::new();
+            let mut answers = Vec::new();
+            for (marker, part) in gift_split(body, &['=', '~']) {
+                let part = part.trim();
+                match marker {
+                    None if part.is_empty() => continue,
+                    None => return Some(Err(ImportError::new(line, "Answers must start with = or ~"))),
+                    Some(marker) => {
+                        // Remove any feedback and weights from the answer
+                        let part = gift_split(part, &['#'])[0].1.trim();
+                        let (weight, part) = match part.strip_prefix('%').and_then(|rest| rest.split_once('%')) {
+                            Some((weight, rest)) => (weight.trim().parse::<f32>().ok(), rest),
+                            None => (None, part),
+                        };
+                        if part.contains("->") {
+                            return Some(Err(ImportError::new(line, "Matching GIFT questions aren't supported")));
+                        }
+                        if marker == '=' || weight.is_some_and(|weight| weight > 0.0) {
+                            answers.push(values.len() as u8);
+                        }
+                        values.push(gift_unescape(part));
+                    }
+                }
+            }
+            if values.len() == answers.len() {
+                Err(ImportError::new(line, "Short answer GIFT questions aren't supported"))
+            } else {
+                question(line, text, values, answers)
+            }
+        }
+    };
+    Some(result)
+}
+
+/// Parses questions written in the GIFT format used by Moodle. Only multiple
+/// choice and true/false questions are supported.
+pub fn parse_gift(text: &str) -> Result<Vec<QuestionData>, Vec<ImportError>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut blocks: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (index, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("//") {
+            continue;
+        }
+        if trimmed.is_empty() {
+            blocks.extend(current.take());
+            continue;
+        }
+        let (_, block) = current.get_or_insert_with(|| (index + 1, String::new()));
+        block.push_str(line);
+        block.push('\n');
+    }
+    blocks.extend(current);
+
+    let mut questions = Vec::new();
+    let mut errors = Vec::new();
+    for (line, block) in blocks {
+        match gift_question(line, &block) {
+            Some(Ok(question)) => questions.push(question),
+            Some(Err(err)) => errors.push(err),
+            None => {}
+        }
+    }
+    if errors.is_empty() && questions.is_empty() {
+        errors.push(ImportError::new(1, "The file doesn't contain any questions"));
+    }
+    if errors.is_empty() {
+        Ok(questions)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Route handler for uploading a question bank in one of the import formats.
+/// The questions are saved to the library as a new quiz titled using the
+/// title query parameter and the response contains its code.
+pub async fn import_questions(
+    req: HttpRequest,
+    path: Path<String>,
+    query: Query<HashMap<String, String>>,
+    body: Bytes,
+    library: Data<QuizLibrary>,
+    address_limiter: Data<AddressLimiter>,
+) -> HttpResponse {
+    let Some(format) = ImportFormat::from_name(&path) else {
+        return HttpResponse::NotFound().json(json!({ "error": "Unknown import format" }));
+    };
+    if let Some(address) = client_address(&req) {
+        if !address_limiter.check_create_game(address) {
+            return HttpResponse::TooManyRequests().json(json!({ "error": "You are doing that too fast" }));
+        }
+    }
+    let Ok(text) = std::str::from_utf8(&body) else {
+        return HttpResponse::BadRequest().json(json!({ "error": "Imported files must be UTF-8 text" }));
+    };
+    let Imported { questions, warnings } = match format.parse(text) {
+        Ok(imported) => imported,
+        Err(errors) => return HttpResponse::BadRequest().json(json!({
+            "errors": errors.iter()
+                .map(|err| json!({ "line": err.line, "message": err.message }))
+                .collect::<Vec<_>>()
+        })),
+    };
+    let title = query.get("title")
+        .map(|title| title.trim())
+        .filter(|title| !title.is_empty())
+        .unwrap_or("Imported Quiz")
+        .to_string();
+    match web::block(move || library.save(&Quiz { title, questions })).await {
+        Ok(Ok(code)) => HttpResponse::Ok().json(json!({
+            "code": code,
+            "warnings": warnings.iter()
+                .map(|warning| json!({ "line": warning.line, "message": warning.message }))
+                .collect::<Vec<_>>()
+        })),
+        Ok(Err(err)) => HttpResponse::InternalServerError().json(json!({ "error": err.reason() })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Imports the question bank at the provided path into the quiz library for
+/// the import subcommand. Returns the code of the saved quiz along with any
+/// warnings about the file.
+pub fn import_file(path: &str, title: Option<&str>) -> Result<(String, Vec<String>), Vec<String>> {
+    let format = ImportFormat::from_path(path)
+        .ok_or_else(|| vec![String::from("Unknown file type, expected a .csv, .gift or .txt file")])?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| vec![format!("Failed to read {}: {}", path, err)])?;
+    let Imported { questions, warnings } = format.parse(&text)
+        .map_err(|errors| errors.iter().map(ImportError::to_string).collect::<Vec<_>>())?;
+    let title = title.map(String::from).unwrap_or_else(|| {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        String::from(name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name))
+    });
+    QuizLibrary::from_env()
+        .save(&Quiz { title, questions })
+        .map(|code| (code, warnings.iter().map(ImportError::to_string).collect()))
+        .map_err(|err| vec![String::from(err.reason())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(errors: &[ImportError]) -> Vec<usize> {
+        errors.iter().map(|err| err.line).collect()
+    }
+
+    #[test]
+    fn csv_with_header() {
+        let imported = parse_csv("Question,Option 1,Option 2,Option 3,Correct\nCapital of France?,Paris,Rome,Berlin,1\nEven numbers?,1,2,4,B;c\n").unwrap();
+        assert!(imported.warnings.is_empty());
+        let questions = imported.questions;
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].question, "Capital of France?");
+        assert_eq!(questions[0].values, ["Paris", "Rome", "Berlin"]);
+        assert_eq!(questions[0].answers, [0]);
+        assert_eq!(questions[1].answers, [1, 2]);
+    }
+
+    #[test]
+    fn csv_positional() {
+        let questions = parse_csv("Capital of Italy?;Paris;Rome;rome;\nSky colour?;Blue;Green;a;\n").unwrap().questions;
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].values, ["Paris", "Rome"]);
+        assert_eq!(questions[0].answers, [1]);
+        assert_eq!(questions[1].answers, [0]);
+    }
+
+    #[test]
+    fn csv_quoted_fields() {
+        let questions = parse_csv("Question,Option 1,Option 2,Correct\n\"Which is \"\"right\"\",\nreally?\",\"a, b\",c,2\n").unwrap().questions;
+        assert_eq!(questions[0].question, "Which is \"right\",\nreally?");
+        assert_eq!(questions[0].values, ["a, b", "c"]);
+        assert_eq!(questions[0].answers, [1]);
+    }
+
+    #[test]
+    fn csv_error_lines() {
+        // The quoted question spans lines 2 and 3 so the following rows are
+        // reported on the line they start on in the file
+        let errors = parse_csv("Question,Option 1,Option 2,Correct\n\"Multi\nline\",a,b,3\n\nOk?,a,b,1\nNo options?,,,1\n,a,b,1\n").unwrap_err();
+        assert_eq!(lines(&errors), [2, 6, 7]);
+        assert_eq!(errors[0].message, "Correct answer \"3\" doesn't match any of the options");
+        assert_eq!(errors[0].to_string(), "line 2: Correct answer \"3\" doesn't match any of the options");
+
+        let errors = parse_csv("Question,Option 1,Option 2,Correct\nOk?,a,b,1\n\"Never closed,a,b,1\n").unwrap_err();
+        assert_eq!(lines(&errors), [3]);
+        assert_eq!(lines(&parse_csv("\n\n").unwrap_err()), [1]);
+    }
+
+    #[test]
+    fn csv_time_limits() {
+        let imported = parse_csv("Question,Option 1,Option 2,Correct,Time Limit\nA?,a,b,1,\nB?,a,b,1,20\nC?,a,b,1,30\n").unwrap();
+        assert_eq!(imported.questions.len(), 3);
+        assert_eq!(lines(&imported.warnings), [3]);
+
+        let errors = parse_csv("Question,Option 1,Option 2,Correct,Time Limit\nA?,a,b,1,0\nB?,a,b,1,soon\n").unwrap_err();
+        assert_eq!(lines(&errors), [2, 3]);
+    }
+
+    #[test]
+    fn gift_questions() {
+        let text = "// A comment\n$CATEGORY: Geography\n\n::Capital::What is the capital of France? {\n=Paris\n~Rome\n~Berlin\n}\n\nThe sky is blue. {T}\n\nPick the primes {~%50%2 ~%50%3 ~%-100%4}\n";
+        let questions = parse_gift(text).unwrap();
+        assert_eq!(questions.len(), 3);
+        assert_eq!(questions[0].question, "What is the capital of France?");
+        assert_eq!(questions[0].values, ["Paris", "Rome", "Berlin"]);
+        assert_eq!(questions[0].answers, [0]);
+        assert_eq!(questions[1].values, ["True", "False"]);
+        assert_eq!(questions[1].answers, [0]);
+        assert_eq!(questions[2].answers, [0, 1]);
+    }
+
+    #[test]
+    fn gift_escapes() {
+        let questions = parse_gift("What is 1 \\= 1? {=yes \\{really\\} #Correct ~no}").unwrap();
+        assert_eq!(questions[0].question, "What is 1 = 1?");
+        assert_eq!(questions[0].values, ["yes {really}", "no"]);
+    }
+
+    #[test]
+    fn gift_error_lines() {
+        let text = "Ok? {=a ~b}\n\nHow many? {#5}\n\nName it {=Paris}\n\n// Comment\nMissing answers\n";
+        let errors = parse_gift(text).unwrap_err();
+        assert_eq!(lines(&errors), [3, 5, 8]);
+        assert_eq!(errors[0].message, "Numerical GIFT questions aren't supported");
+        assert_eq!(errors[1].message, "Short answer GIFT questions aren't supported");
+        assert_eq!(errors[2].message, "Question is missing its {answers}");
+        assert_eq!(lines(&parse_gift("// Only a comment\n").unwrap_err()), [1]);
+    }
+}
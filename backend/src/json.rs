@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Map, Number, Value};
+use wsbps::VarInt;
+use crate::packets::{Blob, DecodeError};
+
+/// Values which can be converted to and from the JSON encoding of the packet
+/// protocol. This is implemented for all the packet types by the protocol
+/// macros so that the JSON and binary encodings never drift apart.
+pub trait JsonValue: Sized {
+    fn to_json(&self) -> Value;
+    fn from_json(value: &Value) -> Result<Self, DecodeError>;
+    /// The name of this type in the protocol schema. Names are spelled out
+    /// rather than taken from [std::any::type_name] which isn't stable
+    /// between compiler versions.
+    fn type_name() -> String;
+}
+
+fn invalid(expected: &str, value: &Value) -> DecodeError {
+    DecodeError::InvalidJson(format!("expected {} but got {}", expected, value))
+}
+
+/// Reads the field with the provided name from a JSON object. Missing fields
+/// are treated as null so that optional fields can be left out.
+pub fn field<T: JsonValue>(object: &Map<String, Value>, name: &str) -> Result<T, DecodeError> {
+    T::from_json(object.get(name).unwrap_or(&Value::Null)).map_err(|err| {
+        // Nested JSON errors are unwrapped so the prefix isn't repeated
+        let err = match err {
+            DecodeError::InvalidJson(err) => err,
+            err => err.to_string(),
+        };
+        DecodeError::InvalidJson(format!("field \"{}\": {}", name, err))
+    })
+}
+
+/// Encodes a server packet as a JSON string
+pub fn encode<T: JsonValue>(value: &T) -> String {
+    value.to_json().to_string()
+}
+
+/// Decodes a client packet from a JSON string
+pub fn decode<T: JsonValue>(text: &str) -> Result<T, DecodeError> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|err| DecodeError::InvalidJson(err.to_string()))?;
+    T::from_json(&value)
+}
+
+macro_rules! json_number {
+    ($($Type:ty: $read:ident),*) => {
+        $(
+            impl JsonValue for $Type {
+                fn to_json(&self) -> Value {
+                    Value::from(*self)
+                }
+
+                fn from_json(value: &Value) -> Result<Self, DecodeError> {
+                    value.$read()
+                        .and_then(|value| <$Type>::try_from(value).ok())
+                        .ok_or_else(|| invalid(stringify!($Type), value))
+                }
+
+                fn type_name() -> String {
+                    String::from(stringify!($Type))
+                }
+            }
+        )*
+    };
+}
+
+json_number! {
+    u8: as_u64,
+    u16: as_u64,
+    u32: as_u64,
+    u64: as_u64,
+    i8: as_i64,
+    i16: as_i64,
+    i32: as_i64,
+    i64: as_i64
+}
+
+impl JsonValue for f32 {
+    fn to_json(&self) -> Value {
+        Number::from_f64(*self as f64).map(Value::Number).unwrap_or(Value::Null)
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_f64().map(|value| value as f32).ok_or_else(|| invalid("f32", value))
+    }
+
+    fn type_name() -> String {
+        String::from("f32")
+    }
+}
+
+impl JsonValue for bool {
+    fn to_json(&self) -> Value {
+        Value::Bool(*self)
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_bool().ok_or_else(|| invalid("bool", value))
+    }
+
+    fn type_name() -> String {
+        String::from("bool")
+    }
+}
+
+impl JsonValue for VarInt {
+    fn to_json(&self) -> Value {
+        Value::from(self.0)
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        u32::from_json(value).map(VarInt)
+    }
+
+    fn type_name() -> String {
+        String::from("VarInt")
+    }
+}
+
+impl JsonValue for String {
+    fn to_json(&self) -> Value {
+        Value::String(self.clone())
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_str().map(String::from).ok_or_else(|| invalid("string", value))
+    }
+
+    fn type_name() -> String {
+        String::from("String")
+    }
+}
+
+impl JsonValue for Blob {
+    fn to_json(&self) -> Value {
+        Value::String(STANDARD.encode(&self.0))
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_str()
+            .and_then(|value| STANDARD.decode(value).ok())
+            .map(Blob)
+            .ok_or_else(|| invalid("base64 string", value))
+    }
+
+    fn type_name() -> String {
+        String::from("Blob")
+    }
+}
+
+impl<T: JsonValue> JsonValue for Vec<T> {
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(T::to_json).collect())
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_array()
+            .ok_or_else(|| invalid("array", value))?
+            .iter()
+            .map(T::from_json)
+            .collect()
+    }
+
+    fn type_name() -> String {
+        format!("Vec<{}>", T::type_name())
+    }
+}
+
+impl<T: JsonValue> JsonValue for Option<T> {
+    fn to_json(&self) -> Value {
+        match self {
+            Some(value) => value.to_json(),
+            None => Value::Null,
+        }
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+
+    fn type_name() -> String {
+        format!("Option<{}>", T::type_name())
+    }
+}
+
+impl<T: JsonValue> JsonValue for HashMap<String, T> {
+    fn to_json(&self) -> Value {
+        Value::Object(self.iter().map(|(key, value)| (key.clone(), value.to_json())).collect())
+    }
+
+    fn from_json(value: &Value) -> Result<Self, DecodeError> {
+        value.as_object()
+            .ok_or_else(|| invalid("object", value))?
+            .iter()
+            .map(|(key, value)| T::from_json(value).map(|value| (key.clone(), value)))
+            .collect()
+    }
+
+    fn type_name() -> String {
+        format!("HashMap<String, {}>", T::type_name())
+    }
+}
+
+/// Converts a wsbps direction into the name used in the protocol schema
+pub fn direction(mode: &str) -> &'static str {
+    match mode.replace(' ', "").as_str() {
+        "(->)" => "clientbound",
+        "(<-)" => "serverbound",
+        _ => "bidirectional",
+    }
+}
+
+pub fn field_schema(name: &str, type_name: String) -> Value {
+    serde_json::json!({ "name": name, "type": type_name })
+}
+
+/// Wraps the wsbps packet_data macro implementing [JsonValue] for the defined
+/// enums and structs. Enums are encoded as the name of their variant and
+/// structs as objects. Also creates a `data_schema` function which describes
+/// all the defined types.
+macro_rules! protocol_data {
+    (@munch [$($Names:ident)*]) => {
+        pub fn data_schema() -> Vec<serde_json::Value> {
+            vec![$($Names::schema()),*]
+        }
+    };
+    (
+        @munch [$($Names:ident)*]
+        enum $Name:ident $Mode:tt ($Type:ty) {
+            $($Field:ident: $Value:literal),* $(,)?
+        }
+        $($rest:tt)*
+    ) => {
+        wsbps::packet_data! {
+            enum $Name $Mode ($Type) {
+                $($Field: $Value),*
+            }
+        }
+
+        impl $Name {
+            pub fn schema() -> serde_json::Value {
+                serde_json::json!({
+                    "name": stringify!($Name),
+                    "kind": "enum",
+                    "direction": $crate::json::direction(stringify!($Mode)),
+                    "repr": <$Type as $crate::json::JsonValue>::type_name(),
+                    "values": [$(serde_json::json!({ "name": stringify!($Field), "value": $Value })),*]
+                })
+            }
+        }
+
+        impl $crate::json::JsonValue for $Name {
+            fn to_json(&self) -> serde_json::Value {
+                serde_json::Value::from(match self {
+                    $($Name::$Field => stringify!($Field),)*
+                })
+            }
+
+            fn from_json(value: &serde_json::Value) -> Result<Self, $crate::packets::DecodeError> {
+                let name = value.as_str().ok_or($crate::packets::DecodeError::InvalidEnumValue)?;
+                $(
+                    if name == stringify!($Field) {
+                        return Ok($Name::$Field);
+                    }
+                )*
+                Err($crate::packets::DecodeError::InvalidEnumValue)
+            }
+
+            fn type_name() -> String {
+                String::from(stringify!($Name))
+            }
+        }
+
+        $crate::json::protocol_data! { @munch [$($Names)* $Name] $($rest)* }
+    };
+    (
+        @munch [$($Names:ident)*]
+        struct $Name:ident $Mode:tt {
+            $($Field:ident: $FieldType:ty),* $(,)?
+        }
+        $($rest:tt)*
+    ) => {
+        wsbps::packet_data! {
+            struct $Name $Mode {
+                $($Field: $FieldType),*
+            }
+        }
+
+        impl $Name {
+            pub fn schema() -> serde_json::Value {
+                serde_json::json!({
+                    "name": stringify!($Name),
+                    "kind": "struct",
+                    "direction": $crate::json::direction(stringify!($Mode)),
+                    "fields": [$($crate::json::field_schema(stringify!($Field), <$FieldType as $crate::json::JsonValue>::type_name())),*]
+                })
+            }
+        }
+
+        impl $crate::json::JsonValue for $Name {
+            fn to_json(&self) -> serde_json::Value {
+                #[allow(unused_mut)]
+                let mut object = serde_json::Map::new();
+                $(object.insert(String::from(stringify!($Field)), $crate::json::JsonValue::to_json(&self.$Field));)*
+                serde_json::Value::Object(object)
+            }
+
+            fn from_json(value: &serde_json::Value) -> Result<Self, $crate::packets::DecodeError> {
+                #[allow(unused_variables)]
+                let object = value.as_object()
+                    .ok_or_else(|| $crate::packets::DecodeError::InvalidJson(String::from("expected an object")))?;
+                Ok($Name {
+                    $($Field: $crate::json::field(object, stringify!($Field))?,)*
+                })
+            }
+
+            fn type_name() -> String {
+                String::from(stringify!($Name))
+            }
+        }
+
+        $crate::json::protocol_data! { @munch [$($Names)* $Name] $($rest)* }
+    };
+    ($($items:tt)*) => {
+        $crate::json::protocol_data! { @munch [] $($items)* }
+    };
+}
+
+/// Wraps the wsbps packets macro implementing [JsonValue] for the defined
+/// packet groups. Packets are encoded as an object containing their fields
+/// along with a "type" field holding the name of the packet. Also creates a
+/// `packets_schema` function which describes all the packet groups.
+macro_rules! protocol_packets {
+    (
+        $(
+            $Group:ident $Mode:tt {
+                $(
+                    $Name:ident ($ID:literal) {
+                        $($Field:ident: $Type:ty),* $(,)?
+                    }
+                )*
+            }
+        )*
+    ) => {
+        wsbps::packets! {
+            $(
+                $Group $Mode {
+                    $(
+                        $Name ($ID) {
+                            $($Field: $Type),*
+                        }
+                    )*
+                }
+            )*
+        }
+
+        pub fn packets_schema() -> Vec<serde_json::Value> {
+            vec![$(
+                serde_json::json!({
+                    "name": stringify!($Group),
+                    "direction": $crate::json::direction(stringify!($Mode)),
+                    "packets": [$(serde_json::json!({
+                        "id": $ID,
+                        "name": stringify!($Name),
+                        "fields": [$($crate::json::field_schema(stringify!($Field), <$Type as $crate::json::JsonValue>::type_name())),*]
+                    })),*]
+                })
+            ),*]
+        }
+
+        $(
+            impl $Group {
+                /// The name of this packet as used by the JSON encoding
+                pub fn name(&self) -> &'static str {
+                    match self {
+                        $($Group::$Name { .. } => stringify!($Name),)*
+                    }
+                }
+            }
+
+            impl $crate::json::JsonValue for $Group {
+                fn to_json(&self) -> serde_json::Value {
+                    let mut object = serde_json::Map::new();
+                    match self {
+                        $(
+                            $Group::$Name { $($Field),* } => {
+                                object.insert(String::from("type"), serde_json::Value::from(stringify!($Name)));
+                                $(object.insert(String::from(stringify!($Field)), $crate::json::JsonValue::to_json($Field));)*
+                            }
+                        )*
+                    }
+                    serde_json::Value::Object(object)
+                }
+
+                fn from_json(value: &serde_json::Value) -> Result<Self, $crate::packets::DecodeError> {
+                    let object = value.as_object()
+                        .ok_or_else(|| $crate::packets::DecodeError::InvalidJson(String::from("expected an object")))?;
+                    let name = object.get("type")
+                        .and_then(|value| value.as_str())
+                        .ok_or_else(|| $crate::packets::DecodeError::InvalidJson(String::from("missing packet type")))?;
+                    $(
+                        if name == stringify!($Name) {
+                            return Ok($Group::$Name {
+                                $($Field: $crate::json::field(object, stringify!($Field))?,)*
+                            });
+                        }
+                    )*
+                    Err($crate::packets::DecodeError::UnknownPacketType(String::from(name)))
+                }
+
+                fn type_name() -> String {
+                    String::from(stringify!($Group))
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use protocol_data;
+pub(crate) use protocol_packets;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::{BanData, ClientPackets, GameState, PlayerDataMode, QuestionData, ResultsFormat, ServerPackets, StateChange};
+
+    fn invalid_json(message: &str) -> DecodeError {
+        DecodeError::InvalidJson(String::from(message))
+    }
+
+    #[test]
+    fn client_packets_round_trip() {
+        let packets = vec![
+            ClientPackets::CreateGame {
+                title: String::from("Capitals"),
+                questions: vec![QuestionData {
+                    image_type: String::from("image/png"),
+                    image: Blob(vec![0x89, 0x50, 0x4E, 0x47]),
+                    question: String::from("Capital of France?"),
+                    values: vec![String::from("Paris"), String::from("Rome")],
+                    answers: vec![0],
+                }],
+            },
+            ClientPackets::StateChange { state: StateChange::Skip },
+            ClientPackets::Answer { id: 3 },
+            ClientPackets::RequestBans {},
+            ClientPackets::RequestResults { format: ResultsFormat::Csv },
+            ClientPackets::Handshake { version: VarInt(2) },
+        ];
+        for packet in packets {
+            assert_eq!(decode::<ClientPackets>(&encode(&packet)), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn server_packets_round_trip() {
+        let packets = vec![
+            ServerPackets::Scores { scores: HashMap::from([(String::from("A1B2"), 300), (String::from("C3D4"), 0)]) },
+            ServerPackets::BanList { bans: vec![BanData { id: String::from("A1B2"), name: String::from("Bob") }] },
+            ServerPackets::PlayerData { id: String::from("A1B2"), name: String::from("Bob"), mode: PlayerDataMode::Me },
+            ServerPackets::TimeSync { total: VarInt(10000), remaining: VarInt(2500) },
+            ServerPackets::Compressed { data: Blob(vec![1, 2, 3]) },
+        ];
+        for packet in packets {
+            assert_eq!(decode::<ServerPackets>(&encode(&packet)), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn packets_are_tagged_with_their_type() {
+        let value = ServerPackets::AnswerResult { result: true }.to_json();
+        assert_eq!(value, serde_json::json!({ "type": "AnswerResult", "result": true }));
+    }
+
+    #[test]
+    fn enums_are_encoded_by_name() {
+        let value = ServerPackets::GameState { state: GameState::Started }.to_json();
+        assert_eq!(value["state"], "Started");
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "StateChange", "state": "Start"}"#),
+            Ok(ClientPackets::StateChange { state: StateChange::Start })
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "StateChange", "state": 1}"#),
+            Err(invalid_json("field \"state\": Packet contained an invalid enum value"))
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "StateChange", "state": "Jump"}"#),
+            Err(invalid_json("field \"state\": Packet contained an invalid enum value"))
+        );
+    }
+
+    #[test]
+    fn blobs_are_base64() {
+        let value = ClientPackets::Compressed { data: Blob(vec![1, 2, 3]) }.to_json();
+        assert_eq!(value["data"], "AQID");
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Compressed", "data": "AQID"}"#),
+            Ok(ClientPackets::Compressed { data: Blob(vec![1, 2, 3]) })
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Compressed", "data": [1, 2, 3]}"#),
+            Err(invalid_json("field \"data\": expected base64 string but got [1,2,3]"))
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Compressed", "data": "not base64!"}"#),
+            Err(invalid_json("field \"data\": expected base64 string but got \"not base64!\""))
+        );
+    }
+
+    #[test]
+    fn field_errors_name_the_field() {
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "RequestJoin", "id": "A1B2C"}"#),
+            Err(invalid_json("field \"name\": expected string but got null"))
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Answer", "id": "one"}"#),
+            Err(invalid_json("field \"id\": expected u8 but got \"one\""))
+        );
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Answer", "id": 256}"#),
+            Err(invalid_json("field \"id\": expected u8 but got 256"))
+        );
+        // Errors in nested structs include the path to the field
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "SaveQuiz", "title": "Quiz", "questions": [{"image_type": "", "image": "", "values": [], "answers": []}]}"#),
+            Err(invalid_json("field \"questions\": field \"question\": expected string but got null"))
+        );
+    }
+
+    #[test]
+    fn unknown_packet_types() {
+        assert_eq!(
+            decode::<ClientPackets>(r#"{"type": "Teleport"}"#),
+            Err(DecodeError::UnknownPacketType(String::from("Teleport")))
+        );
+        assert_eq!(decode::<ClientPackets>(r#"{"id": 3}"#), Err(invalid_json("missing packet type")));
+        assert_eq!(decode::<ClientPackets>("[]"), Err(invalid_json("expected an object")));
+        assert!(matches!(decode::<ClientPackets>("{"), Err(DecodeError::InvalidJson(_))));
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tracing::{error, info};
+use serde_json::{json, Value};
+use crate::images::hash_image;
+use crate::json::JsonValue;
+use crate::packets::{Blob, QuestionData};
+use crate::tools::{Identifier, random_identifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryError {
+    NotFound,
+    Storage,
+    Corrupted,
+}
+
+impl LibraryError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LibraryError::NotFound => "That quiz code doesn't exist",
+            LibraryError::Storage => "Failed to access the quiz library",
+            LibraryError::Corrupted => "That quiz could not be loaded",
+        }
+    }
+}
+
+impl From<io::Error> for LibraryError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            LibraryError::NotFound
+        } else {
+            error!("Quiz library IO error: {}", err);
+            LibraryError::Storage
+        }
+    }
+}
+
+/// A quiz that has been saved to the library
+#[derive(Debug, Clone)]
+pub struct Quiz {
+    pub title: String,
+    pub questions: Vec<QuestionData>,
+}
+
+/// File based storage for saved quizzes. Each quiz is stored as a JSON file
+/// named after its code and the question images are stored separately by
+/// their hash so that they aren't duplicated between quizzes.
+#[derive(Debug, Clone)]
+pub struct QuizLibrary {
+    root: PathBuf,
+}
+
+impl QuizLibrary {
+    pub const CODE_LENGTH: usize = 8;
+
+    pub fn new(root: PathBuf) -> QuizLibrary {
+        QuizLibrary { root }
+    }
+
+    pub fn from_env() -> QuizLibrary {
+        let root = std::env::var("QUIZLER_LIBRARY_PATH").unwrap_or_else(|_| String::from("quizzes"));
+        QuizLibrary::new(PathBuf::from(root))
+    }
+
+    fn quiz_path(&self, code: &str) -> PathBuf {
+        self.root.join(format!("{}.json", code))
+    }
+
+    fn image_path(&self, hash: &str) -> PathBuf {
+        self.root.join("images").join(hash)
+    }
+
+    /// Normalizes the provided quiz code returning None if it could never be
+    /// a valid code. This also prevents codes being used to escape the library.
+    fn normalize_code(code: &str) -> Option<String> {
+        let code = code.trim().to_ascii_uppercase();
+        if code.len() == QuizLibrary::CODE_LENGTH && code.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(code)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the provided file by writing to a temporary file first and then
+    /// renaming it so that partially written files are never read. The
+    /// temporary file has a random name so concurrent saves of the same image
+    /// don't write to the same file.
+    fn write_file(path: &PathBuf, data: &[u8]) -> io::Result<()> {
+        let temp = path.with_extension(format!("{}.tmp", random_identifier(8)));
+        if let Err(err) = fs::write(&temp, data) {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+        fs::rename(&temp, path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+
+    /// Saves the provided quiz to the library returning the code that it can
+    /// be loaded with.
+    pub fn save(&self, quiz: &Quiz) -> Result<Identifier, LibraryError> {
+        fs::create_dir_all(self.root.join("images"))?;
+        let mut questions = Vec::with_capacity(quiz.questions.len());
+        for question in &quiz.questions {
+            let image = if question.image.is_empty() {
+                String::new()
+            } else {
+                let hash = hash_image(&question.image);
+                let path = self.image_path(&hash);
+                if !path.exists() {
+                    QuizLibrary::write_file(&path, &question.image)?;
+                }
+                hash
+            };
+            // The image is stored separately so only its hash is kept in the quiz
+            let mut value = QuestionData { image: Blob::default(), ..question.clone() }.to_json();
+            value["image"] = Value::from(image);
+            questions.push(value);
+        }
+        let contents = json!({ "title": quiz.title, "questions": questions }).to_string();
+
+        let mut code: Identifier;
+        loop {
+            code = random_identifier(QuizLibrary::CODE_LENGTH);
+            if !self.quiz_path(&code).exists() { break; }
+        }
+        QuizLibrary::write_file(&self.quiz_path(&code), contents.as_bytes())?;
+        info!("Saved quiz {} to the library as {}", quiz.title, code);
+        Ok(code)
+    }
+
+    /// Loads the quiz with the provided code from the library along with all
+    /// of its images.
+    pub fn load(&self, code: &str) -> Result<Quiz, LibraryError> {
+        let code = QuizLibrary::normalize_code(code).ok_or(LibraryError::NotFound)?;
+        let contents = fs::read_to_string(self.quiz_path(&code))?;
+        let value: Value = serde_json::from_str(&contents).map_err(|_| LibraryError::Corrupted)?;
+        let title = value["title"].as_str().ok_or(LibraryError::Corrupted)?;
+        let mut questions = Vec::new();
+        for question in value["questions"].as_array().ok_or(LibraryError::Corrupted)? {
+            let hash = question["image"].as_str().ok_or(LibraryError::Corrupted)?;
+            if !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(LibraryError::Corrupted);
+            }
+            let mut question = question.clone();
+            question["image"] = json!("");
+            let mut question = QuestionData::from_json(&question).map_err(|_| LibraryError::Corrupted)?;
+            if !hash.is_empty() {
+                question.image = Blob(fs::read(self.image_path(hash)).map_err(|_| LibraryError::Corrupted)?);
+            }
+            questions.push(question);
+        }
+        Ok(Quiz { title: String::from(title), questions })
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use crate::packets::ClientPackets;
+
+/// Classic token bucket which holds up to `capacity` tokens and is refilled
+/// at a constant rate. Each action takes a single token.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, per_second: f64) -> TokenBucket {
+        TokenBucket {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            per_second,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    pub fn take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+/// The groups of client packets which are limited together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitClass {
+    CreateGame,
+    Lookup,
+    Gameplay,
+}
+
+impl LimitClass {
+    pub fn of(packet: &ClientPackets) -> LimitClass {
+        match packet {
+            ClientPackets::CreateGame { .. }
+            | ClientPackets::SaveQuiz { .. }
+            | ClientPackets::CreateGameFromQuiz { .. } => LimitClass::CreateGame,
+            ClientPackets::CheckNameTaken { .. }
+            | ClientPackets::RequestGameState { .. }
+            | ClientPackets::RequestJoin { .. }
+            | ClientPackets::ResumeGame { .. } => LimitClass::Lookup,
+            _ => LimitClass::Gameplay,
+        }
+    }
+
+    fn bucket(&self) -> TokenBucket {
+        match self {
+            LimitClass::CreateGame => TokenBucket::new(3, 1.0 / 20.0),
+            LimitClass::Lookup => TokenBucket::new(10, 1.0),
+            LimitClass::Gameplay => TokenBucket::new(30, 10.0),
+        }
+    }
+}
+
+/// The outcome of checking a packet against the rate limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitResult {
+    Allowed,
+    Limited,
+    Abusive,
+}
+
+/// Per connection rate limits. Packets which exceed their limit are dropped and
+/// counted as violations, once a connection has racked up too many violations
+/// in a short time it is considered abusive.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    buckets: HashMap<LimitClass, TokenBucket>,
+    violations: TokenBucket,
+}
+
+impl Default for ConnectionLimiter {
+    fn default() -> Self {
+        ConnectionLimiter::new()
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> ConnectionLimiter {
+        ConnectionLimiter {
+            buckets: HashMap::new(),
+            violations: TokenBucket::new(10, 1.0 / 5.0),
+        }
+    }
+
+    pub fn check(&mut self, packet: &ClientPackets) -> LimitResult {
+        let class = LimitClass::of(packet);
+        let allowed = self.buckets
+            .entry(class)
+            .or_insert_with(|| class.bucket())
+            .take();
+        if allowed {
+            LimitResult::Allowed
+        } else {
+            self.violation()
+        }
+    }
+
+    pub fn violation(&mut self) -> LimitResult {
+        if self.violations.take() {
+            LimitResult::Limited
+        } else {
+            LimitResult::Abusive
+        }
+    }
+}
+
+/// Rate limits shared between all the connections from the same IP address.
+/// These are much more lenient than the per connection limits because a whole
+/// classroom will often share the same address.
+#[derive(Debug, Clone, Default)]
+pub struct AddressLimiter {
+    inner: Arc<Mutex<AddressLimits>>,
+}
+
+#[derive(Debug)]
+struct AddressLimits {
+    frames: HashMap<IpAddr, TokenBucket>,
+    games: HashMap<IpAddr, TokenBucket>,
+    quizzes: HashMap<IpAddr, TokenBucket>,
+    last_cleanup: Instant,
+}
+
+impl Default for AddressLimits {
+    fn default() -> Self {
+        AddressLimits {
+            frames: HashMap::new(),
+            games: HashMap::new(),
+            quizzes: HashMap::new(),
+            last_cleanup: Instant::now(),
+        }
+    }
+}
+
+impl AddressLimiter {
+    const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+    fn frame_bucket() -> TokenBucket {
+        TokenBucket::new(500, 100.0)
+    }
+
+    fn game_bucket() -> TokenBucket {
+        TokenBucket::new(10, 1.0 / 30.0)
+    }
+
+    fn quiz_bucket() -> TokenBucket {
+        TokenBucket::new(20, 1.0 / 6.0)
+    }
+
+    /// Checks whether another frame may be received from the provided address
+    pub fn check_frame(&self, address: IpAddr) -> bool {
+        let mut limits = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        limits.cleanup();
+        limits.frames
+            .entry(address)
+            .or_insert_with(AddressLimiter::frame_bucket)
+            .take()
+    }
+
+    /// Checks whether another game may be created from the provided address
+    pub fn check_create_game(&self, address: IpAddr) -> bool {
+        let mut limits = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        limits.games
+            .entry(address)
+            .or_insert_with(AddressLimiter::game_bucket)
+            .take()
+    }
+
+    /// Checks whether another quiz may be downloaded from the library by the
+    /// provided address. Quiz files include the answers so downloads are
+    /// limited to stop the library codes from being guessed.
+    pub fn check_load_quiz(&self, address: IpAddr) -> bool {
+        let mut limits = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        limits.quizzes
+            .entry(address)
+            .or_insert_with(AddressLimiter::quiz_bucket)
+            .take()
+    }
+}
+
+impl AddressLimits {
+    /// Removes the buckets for addresses which haven't been active long enough
+    /// for their buckets to refill completely.
+    fn cleanup(&mut self) {
+        if self.last_cleanup.elapsed() < AddressLimiter::CLEANUP_INTERVAL {
+            return;
+        }
+        self.last_cleanup = Instant::now();
+        self.frames.retain(|_, bucket| !bucket.is_full());
+        self.games.retain(|_, bucket| !bucket.is_full());
+        self.quizzes.retain(|_, bucket| !bucket.is_full());
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use tracing::{error_span, Level, Span};
+use tracing::span::EnteredSpan;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{fmt, EnvFilter, Layer, Registry};
+use tracing_subscriber::fmt::time::ChronoLocal;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use crate::tools::env_value;
+
+/// The details of what the current thread is handling which are attached to
+/// every log event. Set by the actors whenever they handle a message.
+#[derive(Debug, Clone, Default)]
+pub struct LogContext {
+    pub connection: Option<u64>,
+    /// Hash of the session id from `session_hash`
+    pub session: Option<String>,
+    pub game: Option<String>,
+    pub player: Option<String>,
+    pub packet: Option<&'static str>,
+}
+
+impl LogContext {
+    /// Uses this context for log events until the returned guard is dropped.
+    /// The span is created at the error level so that it's enabled for every
+    /// event that is logged.
+    pub fn enter(self) -> EnteredSpan {
+        error_span!(
+            "context",
+            connection = self.connection,
+            session = self.session.as_deref(),
+            game = self.game.as_deref(),
+            player = self.player.as_deref(),
+            packet = self.packet,
+        ).entered()
+    }
+
+    /// Sets the type of packet being handled in the current context
+    pub fn set_packet(packet: &'static str) {
+        Span::current().record("packet", packet);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+pub struct Logger;
+
+impl Logger {
+    /// Creates the layer writing events in the provided format
+    fn layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+        where W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static {
+        let layer = fmt::layer().with_writer(writer);
+        match format {
+            LogFormat::Json => layer.json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .boxed(),
+            LogFormat::Text => layer
+                .with_timer(ChronoLocal::new(String::from("%H:%M:%S")))
+                .with_ansi(ansi)
+                .boxed(),
+        }
+    }
+
+    /// Creates the appender for QUIZLER_LOG_FILE which is rotated on the
+    /// QUIZLER_LOG_ROTATION interval keeping QUIZLER_LOG_MAX_FILES old files
+    fn file_appender(path: &Path) -> io::Result<RollingFileAppender> {
+        let rotation = match std::env::var("QUIZLER_LOG_ROTATION").as_deref() {
+            Ok("minutely") => Rotation::MINUTELY,
+            Ok("hourly") => Rotation::HOURLY,
+            Ok("never") => Rotation::NEVER,
+            _ => Rotation::DAILY,
+        };
+        let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let name = path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "QUIZLER_LOG_FILE must name a file"))?;
+        RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(name)
+            .max_log_files(env_value("QUIZLER_LOG_MAX_FILES", 5usize).max(1))
+            .build(directory)
+            .map_err(io::Error::other)
+    }
+
+    /// Installs the logger configured from the QUIZLER_LOG* environment
+    /// variables. Logs written to a file are flushed when the returned guard
+    /// is dropped so it must be kept until the server stops.
+    pub fn init() -> io::Result<Option<WorkerGuard>> {
+        let default = if cfg!(debug_assertions) { "debug" } else { "info" };
+        let filter = |default: &str| {
+            let spec = std::env::var("QUIZLER_LOG").ok().filter(|spec| !spec.is_empty());
+            EnvFilter::try_new(spec.as_deref().unwrap_or(default)).unwrap_or_else(|_| EnvFilter::new(default))
+        };
+        let format = match std::env::var("QUIZLER_LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        };
+        // Errors go to stderr and everything else to stdout
+        let stdout = io::stderr.with_max_level(Level::ERROR).or_else(io::stdout);
+        let mut layers = vec![
+            Logger::layer(format, stdout, io::stdout().is_terminal()).with_filter(filter(default)).boxed(),
+        ];
+        let mut guard = None;
+        if let Ok(path) = std::env::var("QUIZLER_LOG_FILE").map(std::path::PathBuf::from) {
+            if !path.as_os_str().is_empty() {
+                let (writer, file_guard) = tracing_appender::non_blocking(Logger::file_appender(&path)?);
+                layers.push(Logger::layer(format, writer, false).with_filter(filter(default)).boxed());
+                guard = Some(file_guard);
+            }
+        }
+        tracing_subscriber::registry()
+            .with(layers)
+            .try_init()
+            .map_err(io::Error::other)?;
+        Ok(guard)
+    }
+}
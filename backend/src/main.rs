@@ -2,28 +2,110 @@ extern crate core;
 
 pub mod socket;
 pub mod game;
+pub mod json;
 pub mod packets;
+pub mod names;
+pub mod limits;
+pub mod compression;
+pub mod images;
+pub mod library;
+pub mod import;
+pub mod quiz_file;
+pub mod results;
+pub mod schema;
+pub mod admin;
+pub mod metrics;
+pub mod health;
+pub mod shutdown;
+pub mod snapshot;
+pub mod logging;
+pub mod audit;
+pub mod registry;
 mod tools;
 
 use actix::{Addr};
 use actix_web::{
-    App, HttpRequest, HttpResponse, HttpServer, Responder,
-    web::{Data, get, Payload},
+    App, Error, HttpRequest, HttpResponse, HttpServer,
+    web::{Data, delete, get, Payload, PayloadConfig, post, scope},
 };
-use actix_web_actors::ws::{start};
+use actix_web_actors::ws::WsResponseBuilder;
+use crate::admin::AdminToken;
 use crate::game::GameManager;
-use crate::socket::Connection;
-use simplelog::{ColorChoice, Config, LevelFilter, TerminalMode, TermLogger};
+use crate::images::{ImageLimits, ImageStore, serve_image};
+use crate::import::{import_file, import_questions};
+use crate::library::QuizLibrary;
+use crate::metrics::{Metrics, serve_metrics};
+use crate::health::{HealthDeadline, healthz, readyz};
+use crate::shutdown::shutdown_on_signal;
+use crate::snapshot::SnapshotStore;
+use crate::logging::Logger;
+use crate::audit::{AuditLog, query_audit};
+use crate::registry::{Registry, locate_game};
+use crate::quiz_file::{export_game, export_quiz, import_quiz};
+use crate::results::{ResultsStore, serve_results};
+use crate::limits::AddressLimiter;
+use crate::socket::{Connection, Encoding, Session};
+use crate::tools::env_value;
 
 const APP_INDEX: &str = include_str!("../public/index.html");
+const MAX_FRAME_SIZE: usize = 32 * 1024 * 1024;
+/// Seconds that connections have to close once games have been shut down
+const SHUTDOWN_TIMEOUT: u64 = 5;
+
+/// Runs the subcommand from the command line arguments if there is one.
+/// Returns whether a command was run, exiting for unknown commands.
+fn run_command() -> bool {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        [] => false,
+        ["schema"] => {
+            println!("{:#}", schema::schema());
+            true
+        }
+        ["schema", "markdown"] => {
+            print!("{}", schema::markdown());
+            true
+        }
+        ["import", path] | ["import", path, _] => {
+            match import_file(path, args.get(2).map(String::as_str)) {
+                Ok((code, warnings)) => {
+                    for warning in warnings {
+                        eprintln!("warning: {}", warning);
+                    }
+                    println!("Imported {} as quiz code {}", path, code)
+                }
+                Err(errors) => {
+                    for err in errors {
+                        eprintln!("{}", err);
+                    }
+                    std::process::exit(1);
+                }
+            }
+            true
+        }
+        _ => {
+            eprintln!(
+                "Unknown command. Available commands:\n  \
+                schema [markdown]     Prints the packet protocol schema\n  \
+                import <file> [title] Imports a CSV or GIFT question bank into the quiz library"
+            );
+            std::process::exit(2);
+        }
+    }
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    const INTRO: &'static str =
+    if run_command() {
+        return Ok(());
+    }
+    const INTRO: &str =
         "   __         __       ___  __  \n\
               /  \\ |  | |  / |    |__  |__) \n\
                 \\__X \\__/ | /_ |___ |___ |  \\   by Jacobtread\n\n";
-    let port: u16 = option_env!("PORT").unwrap_or("8080").parse::<u16>().expect("expected port to be u16 number");
+    // Each replica of the server needs its own address to bind on
+    let address: String = env_value("QUIZLER_ADDRESS", String::from("127.0.0.1"));
+    let port: u16 = env_value("QUIZLER_PORT", 8080);
     println!(
         concat!(
         "{} Version ",
@@ -32,38 +114,88 @@ async fn main() -> std::io::Result<()> {
         ), INTRO, port
     );
 
-    #[cfg(debug_assertions)]
-    {
-        TermLogger::init(
-            LevelFilter::Debug,
-            Config::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        );
-    }
-
-    #[cfg(not(debug_assertions))]
-    {
-        TermLogger::init(
-            LevelFilter::Info,
-            Config::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        );
-    }
+    let _log_guard = Logger::init()?;
 
-    let manager = GameManager::new();
-    HttpServer::new(move || {
+    let images = Data::new(ImageStore::default());
+    let library = Data::new(QuizLibrary::from_env());
+    let image_limits = Data::new(ImageLimits::from_env());
+    let results = Data::new(ResultsStore::from_env());
+    let metrics = Data::new(Metrics::default());
+    let audit = Data::new(AuditLog::from_env());
+    let registry = Data::new(Registry::from_env()?);
+    let manager = GameManager::new(
+        images.get_ref().clone(),
+        library.get_ref().clone(),
+        *image_limits.get_ref(),
+        results.get_ref().clone(),
+        metrics.get_ref().clone(),
+        SnapshotStore::from_env(),
+        audit.get_ref().clone(),
+        registry.get_ref().clone(),
+    );
+    let admin_token = Data::new(AdminToken::from_env());
+    let health_deadline = Data::new(HealthDeadline::from_env());
+    let max_frame_size = env_value("QUIZLER_MAX_FRAME_SIZE", MAX_FRAME_SIZE);
+    let address_limiter = Data::new(AddressLimiter::default());
+    let shutdown_manager = manager.get_ref().clone();
+    let shutdown_audit = audit.get_ref().clone();
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(manager.clone())
-            .route("/ws", get().to(|req: HttpRequest, stream: Payload, manager: Data<Addr<GameManager>>| async move {
-                start(Connection::new(manager.get_ref().clone()), &req, stream)
+            .app_data(address_limiter.clone())
+            .app_data(images.clone())
+            .app_data(library.clone())
+            .app_data(image_limits.clone())
+            .app_data(results.clone())
+            .app_data(admin_token.clone())
+            .app_data(metrics.clone())
+            .app_data(health_deadline.clone())
+            .app_data(audit.clone())
+            .app_data(registry.clone())
+            .app_data(PayloadConfig::new(max_frame_size))
+            .route("/ws", get().to(move |req: HttpRequest, stream: Payload, manager: Data<Addr<GameManager>>, address_limiter: Data<AddressLimiter>, metrics: Data<Metrics>| async move {
+                let session = Session::from_request(&req);
+                let cookie = session.cookie();
+                let encoding = Encoding::from_request(&req);
+                let connection = Connection::new(manager.get_ref().clone(), session, address_limiter.get_ref().clone(), metrics.get_ref().clone(), encoding);
+                let stream = connection.track_activity(stream);
+                let mut res = WsResponseBuilder::new(connection, &req, stream)
+                    .protocols(Encoding::PROTOCOLS)
+                    .frame_size(max_frame_size)
+                    .start()?;
+                res.add_cookie(&cookie)?;
+                Ok::<HttpResponse, Error>(res)
+            }))
+            .route("/images/{game}/{hash}", get().to(serve_image))
+            .route("/quizzes", post().to(import_quiz))
+            .route("/quizzes/import/{format}", post().to(import_questions))
+            .route("/quizzes/{code}", get().to(export_quiz))
+            .route("/games/{id}/quiz", get().to(export_game))
+            .route("/games/{id}/results", get().to(serve_results))
+            .route("/games/{id}/node", get().to(locate_game))
+            .service(scope("/admin")
+                .route("/games", get().to(admin::list_games))
+                .route("/games/{id}", get().to(admin::get_game))
+                .route("/games/{id}", delete().to(admin::end_game))
+                .route("/games/{id}/players/{player}", delete().to(admin::kick_player))
+                .route("/audit", get().to(query_audit)))
+            .route("/metrics", get().to(serve_metrics))
+            .route("/healthz", get().to(healthz))
+            .route("/readyz", get().to(readyz))
+            .route("/schema.json", get().to(|| async {
+                HttpResponse::Ok().json(schema::schema())
             }))
             .route("/{_:.*}", get().to(|| async {
                 HttpResponse::Ok().content_type("text/html").body(APP_INDEX)
             }))
     })
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+        .disable_signals()
+        .shutdown_timeout(SHUTDOWN_TIMEOUT)
+        .bind((address, port))?
+        .run();
+    actix_web::rt::spawn(shutdown_on_signal(server.handle(), shutdown_manager));
+    let result = server.await;
+    // Games record that they ended while shutting down
+    shutdown_audit.flush();
+    result
 }
\ No newline at end of file
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use actix_web::{HttpResponse, web::Data};
+
+/// Counters describing the state of the server which are exposed in the
+/// Prometheus text format. Cloning shares the same underlying counters.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<MetricsInner>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    connections: AtomicU64,
+    games: AtomicU64,
+    players: AtomicU64,
+    decode_errors: AtomicU64,
+    ticks: AtomicU64,
+    tick_nanos: AtomicU64,
+    mailbox_messages: AtomicU64,
+    mailbox_nanos: AtomicU64,
+    packets_in: Mutex<BTreeMap<&'static str, u64>>,
+    packets_out: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// Calculates the average of a total number of nanoseconds in seconds
+fn average_seconds(total: &AtomicU64, count: &AtomicU64) -> f64 {
+    let count = count.load(Ordering::Relaxed);
+    if count == 0 {
+        0.0
+    } else {
+        total.load(Ordering::Relaxed) as f64 / count as f64 / 1e9
+    }
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.inner.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.inner.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn decode_error(&self) {
+        self.inner.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_in(&self, name: &'static str) {
+        *self.inner.packets_in.lock().unwrap().entry(name).or_default() += 1;
+    }
+
+    pub fn packet_out(&self, name: &'static str) {
+        *self.inner.packets_out.lock().unwrap().entry(name).or_default() += 1;
+    }
+
+    /// Records a game starting along with the players it already has
+    /// (restored games start with their players)
+    pub fn game_started(&self, players: usize) {
+        self.inner.games.fetch_add(1, Ordering::Relaxed);
+        self.inner.players.fetch_add(players as u64, Ordering::Relaxed);
+    }
+
+    /// Records a game stopping along with the players still in it
+    pub fn game_stopped(&self, players: usize) {
+        self.inner.games.fetch_sub(1, Ordering::Relaxed);
+        self.inner.players.fetch_sub(players as u64, Ordering::Relaxed);
+    }
+
+    pub fn player_joined(&self) {
+        self.inner.players.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn player_left(&self) {
+        self.inner.players.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// The number of players in active games
+    pub fn players(&self) -> usize {
+        self.inner.players.load(Ordering::Relaxed) as usize
+    }
+
+    /// Records the time taken for a game to tick
+    pub fn tick(&self, duration: Duration) {
+        self.inner.ticks.fetch_add(1, Ordering::Relaxed);
+        self.inner.tick_nanos.fetch_add(nanos(duration), Ordering::Relaxed);
+    }
+
+    /// Records the time taken for the game manager or a game to respond to a
+    /// message including the time the message spent waiting in its mailbox.
+    pub fn mailbox(&self, duration: Duration) {
+        self.inner.mailbox_messages.fetch_add(1, Ordering::Relaxed);
+        self.inner.mailbox_nanos.fetch_add(nanos(duration), Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let inner = &self.inner;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in values {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let single = |value: u64| vec![(String::new(), value.to_string())];
+        let by_type = |counts: &Mutex<BTreeMap<&'static str, u64>>| counts.lock().unwrap()
+            .iter()
+            .map(|(name, count)| (format!("{{type=\"{}\"}}", name), count.to_string()))
+            .collect::<Vec<_>>();
+
+        metric("quizler_games", "gauge", "Number of active games",
+               single(inner.games.load(Ordering::Relaxed)));
+        metric("quizler_players", "gauge", "Number of players in active games",
+               single(inner.players.load(Ordering::Relaxed)));
+        metric("quizler_connections", "gauge", "Number of open websocket connections",
+               single(inner.connections.load(Ordering::Relaxed)));
+        metric("quizler_packets_in_total", "counter", "Packets received from clients by type",
+               by_type(&inner.packets_in));
+        metric("quizler_packets_out_total", "counter", "Packets sent to clients by type",
+               by_type(&inner.packets_out));
+        metric("quizler_decode_errors_total", "counter", "Malformed packets received from clients",
+               single(inner.decode_errors.load(Ordering::Relaxed)));
+        metric("quizler_tick_seconds_average", "gauge", "Average duration of a game tick",
+               vec![(String::new(), average_seconds(&inner.tick_nanos, &inner.ticks).to_string())]);
+        metric("quizler_ticks_total", "counter", "Game ticks",
+               single(inner.ticks.load(Ordering::Relaxed)));
+        metric("quizler_mailbox_latency_seconds_average", "gauge", "Average time for the game manager or a game to respond to a connection",
+               vec![(String::new(), average_seconds(&inner.mailbox_nanos, &inner.mailbox_messages).to_string())]);
+        metric("quizler_mailbox_messages_total", "counter", "Messages sent to the game manager and games by connections",
+               single(inner.mailbox_messages.load(Ordering::Relaxed)));
+        out
+    }
+}
+
+/// Route handler for scraping the metrics
+pub async fn serve_metrics(metrics: Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use tracing::{error, info};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+pub const MIN_NAME_LENGTH: usize = 1;
+pub const MAX_NAME_LENGTH: usize = 12;
+
+const ALLOWED_SYMBOLS: &[char] = &[' ', '-', '_', '.', '\''];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameError {
+    TooShort,
+    TooLong,
+    InvalidCharacters,
+    Inappropriate,
+    Taken,
+}
+
+impl NameError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            NameError::TooShort => "That name is too short",
+            NameError::TooLong => "That name is too long",
+            NameError::InvalidCharacters => "That name contains characters that aren't allowed",
+            NameError::Inappropriate => "That name isn't allowed",
+            NameError::Taken => "That name is already in use",
+        }
+    }
+}
+
+/// Cleans up a name for display by applying NFKC normalization, trimming it
+/// and collapsing any runs of whitespace into a single space.
+pub fn clean_name(name: &str) -> String {
+    let normalized: String = name.nfkc().collect();
+    normalized.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Folds the case and unicode representation of a name
+fn fold_name(name: &str) -> String {
+    clean_name(name).to_lowercase().nfkc().collect()
+}
+
+/// Reduces text to its UTS #39 skeleton so that characters which look the
+/// same, such as the Cyrillic and Latin a, become the same character.
+fn skeleton_key(text: &str) -> String {
+    skeleton(text).collect::<String>().to_lowercase()
+}
+
+/// Creates the key used to compare names against each other so that names
+/// which only differ by case, by their unicode representation or by using
+/// confusable characters from other scripts collide.
+pub fn name_key(name: &str) -> String {
+    skeleton_key(&fold_name(name))
+}
+
+/// Validates a cleaned name against the length and character rules
+pub fn check_name(name: &str) -> Result<(), NameError> {
+    let length = name.chars().count();
+    if length < MIN_NAME_LENGTH {
+        return Err(NameError::TooShort);
+    }
+    if length > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong);
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || ALLOWED_SYMBOLS.contains(&c)) {
+        return Err(NameError::InvalidCharacters);
+    }
+    Ok(())
+}
+
+/// Word list based filter for player names. The list is loaded from a plain
+/// text file containing one word per line where lines starting with # are
+/// ignored.
+#[derive(Debug, Default)]
+pub struct NameFilter {
+    words: HashSet<String>,
+}
+
+impl NameFilter {
+    pub fn load(path: &str) -> NameFilter {
+        match read_to_string(path) {
+            Ok(contents) => {
+                let filter = NameFilter::from_words(contents.lines());
+                info!("Loaded {} words into the name filter from {}", filter.words.len(), path);
+                filter
+            }
+            Err(err) => {
+                error!("Failed to load name filter word list from {}: {}", path, err);
+                NameFilter::default()
+            }
+        }
+    }
+
+    pub fn from_env() -> NameFilter {
+        match std::env::var("QUIZLER_WORD_LIST") {
+            Ok(path) => NameFilter::load(&path),
+            Err(_) => NameFilter::default(),
+        }
+    }
+
+    pub fn from_words<'a, I: Iterator<Item=&'a str>>(words: I) -> NameFilter {
+        NameFilter {
+            words: words
+                .map(str::trim)
+                .filter(|word| !word.is_empty() && !word.starts_with('#'))
+                .map(|word| skeleton_key(&simplify(&fold_name(word))))
+                .collect()
+        }
+    }
+
+    /// Checks whether any of the words in the name, or the name as a whole
+    /// with its separators removed, is present in the word list.
+    pub fn is_blocked(&self, name: &str) -> bool {
+        if self.words.is_empty() {
+            return false;
+        }
+        // Substitutions are undone before the skeleton is taken as digits
+        // like 1 are confusable with letters other than the one they replace
+        let key = fold_name(name);
+        let squashed = skeleton_key(&simplify(&key));
+        self.words.contains(&squashed) || key
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| !word.is_empty() && self.words.contains(&skeleton_key(&simplify(word))))
+    }
+
+    /// Cleans the provided name and ensures that it is valid and allowed,
+    /// returning the cleaned name that should be used.
+    pub fn validate(&self, name: &str) -> Result<String, NameError> {
+        let name = clean_name(name);
+        check_name(&name)?;
+        if self.is_blocked(&name) {
+            return Err(NameError::Inappropriate);
+        }
+        Ok(name)
+    }
+}
+
+/// Reduces a name key to only its alphanumeric characters and undoes common
+/// character substitutions used to get around the word list.
+fn simplify(key: &str) -> String {
+    key.chars()
+        .filter_map(|c| match c {
+            '0' => Some('o'),
+            '1' => Some('i'),
+            '3' => Some('e'),
+            '4' => Some('a'),
+            '5' => Some('s'),
+            '7' => Some('t'),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(words: &[&str]) -> NameFilter {
+        NameFilter::from_words(words.iter().copied())
+    }
+
+    #[test]
+    fn cleans_whitespace_and_normalizes() {
+        assert_eq!(clean_name("  Bob   the\tBuilder "), "Bob the Builder");
+        // Full width letters and ligatures are replaced by their plain forms
+        assert_eq!(clean_name("Ｂｏｂ"), "Bob");
+        assert_eq!(clean_name("ﬁn"), "fin");
+    }
+
+    #[test]
+    fn keys_collide_for_lookalike_names() {
+        assert_eq!(name_key("BOB"), name_key("bob"));
+        assert_eq!(name_key(" Bob  Smith "), name_key("bob smith"));
+        // Cyrillic "а" and Latin "a"
+        assert_eq!(name_key("B\u{0430}rt"), name_key("Bart"));
+        // Cyrillic "о" and Latin "o"
+        assert_eq!(name_key("B\u{043E}b"), name_key("Bob"));
+        assert_ne!(name_key("Bob"), name_key("Rob"));
+    }
+
+    #[test]
+    fn checks_length_and_characters() {
+        assert_eq!(check_name(""), Err(NameError::TooShort));
+        assert_eq!(check_name("abcdefghijkl"), Ok(()));
+        assert_eq!(check_name("abcdefghijklm"), Err(NameError::TooLong));
+        assert_eq!(check_name("O'Neil-Jr."), Ok(()));
+        assert_eq!(check_name("Zoë 李"), Ok(()));
+        assert_eq!(check_name("Bob!"), Err(NameError::InvalidCharacters));
+        assert_eq!(check_name("Bob\u{1F600}"), Err(NameError::InvalidCharacters));
+    }
+
+    #[test]
+    fn simplify_undoes_substitutions() {
+        assert_eq!(simplify("b0b"), "bob");
+        assert_eq!(simplify("l33t"), "leet");
+        assert_eq!(simplify("b-o.b"), "bob");
+    }
+
+    #[test]
+    fn filter_blocks_listed_words() {
+        let filter = filter(&["bob", "# comment", "", "  "]);
+        assert_eq!(filter.words.len(), 1);
+        assert!(filter.is_blocked("bob"));
+        assert!(filter.is_blocked("B0B"));
+        assert!(filter.is_blocked("Big Bob"));
+        assert!(filter.is_blocked("b.o.b"));
+        assert!(filter.is_blocked("B\u{043E}b"));
+        assert!(!filter.is_blocked("Bobby"));
+        assert!(!filter.is_blocked("Rob"));
+        assert!(!filter.is_blocked("comment"));
+    }
+
+    #[test]
+    fn empty_filter_blocks_nothing() {
+        assert!(!NameFilter::default().is_blocked("bob"));
+    }
+
+    #[test]
+    fn validate_returns_the_cleaned_name() {
+        let filter = filter(&["bob"]);
+        assert_eq!(filter.validate("  Jane   Doe "), Ok(String::from("Jane Doe")));
+        assert_eq!(filter.validate("   "), Err(NameError::TooShort));
+        assert_eq!(filter.validate("B0B"), Err(NameError::Inappropriate));
+    }
+}
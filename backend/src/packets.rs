@@ -1,8 +1,12 @@
 use std::collections::HashMap;
-use wsbps::{packet_data, packets, VarInt};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::ops::{Deref, DerefMut};
+use wsbps::{PacketError, Readable, ReadResult, VarInt, Writable, WriteResult};
+use crate::json::{protocol_data, protocol_packets};
 use crate::tools::Identifier;
 
-packet_data! {
+protocol_data! {
 
     enum GameState (->) (u8) {
         Waiting: 0,
@@ -24,9 +28,19 @@ packet_data! {
         Skip: 2
     }
 
+    enum ResultsFormat (<->) (u8) {
+        Json: 0,
+        Csv: 1
+    }
+
+    struct BanData (->) {
+        id: Identifier,
+        name: String
+    }
+
     struct QuestionData (<-) {
         image_type: String,
-        image: Vec<u8>,
+        image: Blob,
         question: String,
         values: Vec<String>,
         answers: Vec<u8>
@@ -35,18 +49,78 @@ packet_data! {
 
 pub type ScoresMap = HashMap<String, u32>;
 
-packets! {
+/// Raw bytes such as images and compressed packets. These are encoded the
+/// same as a `Vec<u8>` in the binary protocol and as a base64 string in the
+/// JSON protocol rather than an array of numbers.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Blob(pub Vec<u8>);
+
+impl Debug for Blob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Blob({} bytes)", self.0.len())
+    }
+}
+
+impl Deref for Blob {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Blob {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl From<Vec<u8>> for Blob {
+    fn from(data: Vec<u8>) -> Self {
+        Blob(data)
+    }
+}
+
+impl Readable for Blob {
+    fn read<B: Read>(i: &mut B) -> ReadResult<Self> where Self: Sized {
+        Vec::<u8>::read(i).map(Blob)
+    }
+}
+
+impl Writable for Blob {
+    fn write<B: Write>(&mut self, o: &mut B) -> WriteResult {
+        Writable::write(&mut self.0, o)
+    }
+}
+
+/// The version of the protocol described by the packets below. This must be
+/// incremented whenever a change is made that older clients can't understand.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// The oldest protocol version that the server is still able to talk to
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+// The handshake packets both use the id 0x7F which must never change so that
+// clients of any version are able to negotiate with the server.
+
+protocol_packets! {
     ServerPackets (->) {
         Disconnect (0x00) { reason: String }
         Error (0x01) { cause: String }
         JoinedGame (0x02) { id: Identifier, owner: bool, title: String}
-        NameTakenResult (0x03) { result: bool }
+        NameTakenResult (0x03) { result: bool, reason: String }
         GameState (0x04) { state: GameState }
         PlayerData (0x05) { id: Identifier, name: String, mode: PlayerDataMode }
         TimeSync (0x06) { total: VarInt, remaining: VarInt}
-        Question (0x07) { image: Vec<u8>, question: String, answers: Vec<String> }
+        Question (0x07) { image: String, question: String, answers: Vec<String> }
         AnswerResult (0x08) { result: bool }
         Scores (0x09) { scores: ScoresMap }
+        BanList (0x0A) { bans: Vec<BanData> }
+        Compressed (0x0B) { data: Blob }
+        SetCompression (0x0C) { enabled: bool }
+        QuizSaved (0x0D) { code: String }
+        Results (0x0E) { format: ResultsFormat, data: String }
+        Redirect (0x0F) { id: Identifier, url: String }
+        HandshakeResult (0x7F) { accepted: bool, min_version: VarInt, max_version: VarInt }
     }
 
     ClientPackets (<-) {
@@ -57,5 +131,117 @@ packets! {
         StateChange (0x04) { state: StateChange }
         Answer (0x05) { id: u8 }
         Kick (0x06) { id: Identifier }
+        RequestBans (0x07) {}
+        Unban (0x08) { id: Identifier }
+        SetCompression (0x09) { enabled: bool }
+        Compressed (0x0A) { data: Blob }
+        SaveQuiz (0x0B) { title: String, questions: Vec<QuestionData> }
+        CreateGameFromQuiz (0x0C) { code: String }
+        RequestResults (0x0D) { format: ResultsFormat }
+        ResumeGame (0x0E) { id: Identifier }
+        Handshake (0x7F) { version: VarInt }
+    }
+}
+
+/// The reasons that a client packet can fail to decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownPacket(u32),
+    Truncated,
+    InvalidEnumValue,
+    InvalidString,
+    InvalidVarInt,
+    TrailingData(usize),
+    InvalidJson(String),
+    UnknownPacketType(String),
+    InvalidCompression,
+    Other(String),
+}
+
+impl From<PacketError> for DecodeError {
+    fn from(err: PacketError) -> Self {
+        match err {
+            PacketError::UnknownPacket(id) => DecodeError::UnknownPacket(id),
+            PacketError::IO(err) if err.kind() == ErrorKind::UnexpectedEof => DecodeError::Truncated,
+            PacketError::UnknownEnumValue => DecodeError::InvalidEnumValue,
+            PacketError::BadEncoding(_) | PacketError::InvalidStringLength(..) => DecodeError::InvalidString,
+            PacketError::VarOverflow(..) => DecodeError::InvalidVarInt,
+            err => DecodeError::Other(err.to_string()),
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownPacket(id) => write!(f, "Unknown packet id 0x{:02X}", id),
+            DecodeError::Truncated => f.write_str("Packet ended before all of its fields were read"),
+            DecodeError::InvalidEnumValue => f.write_str("Packet contained an invalid enum value"),
+            DecodeError::InvalidString => f.write_str("Packet contained an invalid string"),
+            DecodeError::InvalidVarInt => f.write_str("Packet contained an invalid VarInt"),
+            DecodeError::TrailingData(length) => write!(f, "Packet had {} unexpected trailing bytes", length),
+            DecodeError::InvalidJson(err) => write!(f, "Invalid JSON packet: {}", err),
+            DecodeError::InvalidCompression => f.write_str("Compressed packet was invalid or too large"),
+            DecodeError::UnknownPacketType(name) => write!(f, "Unknown packet type \"{}\"", name),
+            DecodeError::Other(err) => write!(f, "Malformed packet: {}", err),
+        }
+    }
+}
+
+/// Decodes a single client packet which must take up the entire provided buffer
+pub fn decode_client_packet(bytes: &[u8]) -> Result<ClientPackets, DecodeError> {
+    let mut cursor = Cursor::new(bytes);
+    let packet = ClientPackets::read(&mut cursor)?;
+    let remaining = bytes.len() - cursor.position() as usize;
+    if remaining > 0 {
+        return Err(DecodeError::TrailingData(remaining));
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_complete_packets() {
+        assert_eq!(decode_client_packet(&[0x05, 0x03]), Ok(ClientPackets::Answer { id: 3 }));
+        assert_eq!(decode_client_packet(&[0x04, 0x02]), Ok(ClientPackets::StateChange { state: StateChange::Skip }));
+        assert_eq!(
+            decode_client_packet(&[0x03, 0x02, b'A', b'B', 0x03, b'B', b'o', b'b']),
+            Ok(ClientPackets::RequestJoin { id: String::from("AB"), name: String::from("Bob") })
+        );
+    }
+
+    #[test]
+    fn truncated_packets() {
+        assert_eq!(decode_client_packet(&[]), Err(DecodeError::Truncated));
+        assert_eq!(decode_client_packet(&[0x05]), Err(DecodeError::Truncated));
+        // The name claims to be 5 bytes long but only 1 byte follows
+        assert_eq!(decode_client_packet(&[0x01, 0x00, 0x05, b'B']), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn unknown_packet_ids() {
+        assert_eq!(decode_client_packet(&[0x50]), Err(DecodeError::UnknownPacket(0x50)));
+        assert_eq!(DecodeError::UnknownPacket(0x50).to_string(), "Unknown packet id 0x50");
+    }
+
+    #[test]
+    fn invalid_enum_values() {
+        assert_eq!(decode_client_packet(&[0x04, 0x09]), Err(DecodeError::InvalidEnumValue));
+        assert_eq!(decode_client_packet(&[0x0D, 0x02]), Err(DecodeError::InvalidEnumValue));
+    }
+
+    #[test]
+    fn invalid_strings_and_var_ints() {
+        assert_eq!(decode_client_packet(&[0x02, 0x02, 0xFF, 0xFE]), Err(DecodeError::InvalidString));
+        assert_eq!(decode_client_packet(&[0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), Err(DecodeError::InvalidVarInt));
+    }
+
+    #[test]
+    fn trailing_data() {
+        assert_eq!(decode_client_packet(&[0x05, 0x03, 0x00, 0x00]), Err(DecodeError::TrailingData(2)));
+        assert_eq!(decode_client_packet(&[0x07, 0x01]), Err(DecodeError::TrailingData(1)));
     }
 }
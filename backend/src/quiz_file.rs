@@ -0,0 +1,198 @@
+use actix::Addr;
+use actix_web::{HttpRequest, HttpResponse, web::{self, Bytes, Data, Path}};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{json, Map, Value};
+use crate::game::{ExportGame, GameManager};
+use crate::images::ImageLimits;
+use crate::library::{Quiz, QuizLibrary};
+use crate::limits::AddressLimiter;
+use crate::packets::{Blob, QuestionData};
+use crate::socket::Session;
+use crate::tools::client_address;
+
+/// The value of the format field which identifies quiz files
+pub const QUIZ_FILE_FORMAT: &str = "quizler.quiz";
+/// The version of the quiz file format written by this server. This must be
+/// incremented whenever a change is made that older servers can't read.
+pub const QUIZ_FILE_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuizFileError {
+    InvalidJson,
+    NotQuizFile,
+    UnsupportedVersion,
+    MissingField,
+    InvalidImage,
+}
+
+impl QuizFileError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            QuizFileError::InvalidJson => "That quiz file isn't valid JSON",
+            QuizFileError::NotQuizFile => "That file isn't a quiz file",
+            QuizFileError::UnsupportedVersion => "That quiz file was made by a newer version of Quizler",
+            QuizFileError::MissingField => "That quiz file is missing required fields",
+            QuizFileError::InvalidImage => "That quiz file contains an invalid image",
+        }
+    }
+}
+
+/// Encodes the provided quiz as a quiz file. Quiz files are JSON documents
+/// with the question images embedded as base64 strings.
+pub fn encode(quiz: &Quiz) -> String {
+    let questions: Vec<Value> = quiz.questions
+        .iter()
+        .map(|question| json!({
+            "question": question.question,
+            "answers": question.values,
+            "correct": question.answers,
+            "image_type": question.image_type,
+            "image": STANDARD.encode(&question.image.0),
+        }))
+        .collect();
+    json!({
+        "format": QUIZ_FILE_FORMAT,
+        "version": QUIZ_FILE_VERSION,
+        "title": quiz.title,
+        "questions": questions,
+    }).to_string()
+}
+
+fn string(object: &Map<String, Value>, key: &str) -> Result<String, QuizFileError> {
+    object.get(key)
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or(QuizFileError::MissingField)
+}
+
+fn decode_question(value: &Value) -> Result<QuestionData, QuizFileError> {
+    let object = value.as_object().ok_or(QuizFileError::MissingField)?;
+    let image = match object.get("image").and_then(Value::as_str) {
+        None | Some("") => Vec::new(),
+        Some(image) => STANDARD.decode(image).map_err(|_| QuizFileError::InvalidImage)?,
+    };
+    Ok(QuestionData {
+        image_type: if image.is_empty() { String::new() } else { string(object, "image_type")? },
+        image: Blob(image),
+        question: string(object, "question")?,
+        values: object.get("answers")
+            .and_then(Value::as_array)
+            .ok_or(QuizFileError::MissingField)?
+            .iter()
+            .map(|value| value.as_str().map(String::from).ok_or(QuizFileError::MissingField))
+            .collect::<Result<_, _>>()?,
+        answers: object.get("correct")
+            .and_then(Value::as_array)
+            .ok_or(QuizFileError::MissingField)?
+            .iter()
+            .map(|value| value.as_u64()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or(QuizFileError::MissingField))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// Decodes a quiz file checking that it's a version this server understands
+pub fn decode(data: &[u8]) -> Result<Quiz, QuizFileError> {
+    let value: Value = serde_json::from_slice(data).map_err(|_| QuizFileError::InvalidJson)?;
+    let object = value.as_object().ok_or(QuizFileError::NotQuizFile)?;
+    if object.get("format").and_then(Value::as_str) != Some(QUIZ_FILE_FORMAT) {
+        return Err(QuizFileError::NotQuizFile);
+    }
+    match object.get("version").and_then(Value::as_u64) {
+        Some(version) if version <= QUIZ_FILE_VERSION => {}
+        Some(_) => return Err(QuizFileError::UnsupportedVersion),
+        None => return Err(QuizFileError::MissingField),
+    }
+    Ok(Quiz {
+        title: string(object, "title")?,
+        questions: object.get("questions")
+            .and_then(Value::as_array)
+            .ok_or(QuizFileError::MissingField)?
+            .iter()
+            .map(decode_question)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn error_response(reason: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "error": reason }))
+}
+
+/// Creates a response which downloads the provided quiz as a quiz file
+fn file_response(quiz: &Quiz) -> HttpResponse {
+    let name: String = quiz.title
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{}.quiz", name))],
+        })
+        .body(encode(quiz))
+}
+
+/// Route handler for uploading a quiz file. The quiz is saved to the library
+/// and the response contains the code which can be used to start a game.
+pub async fn import_quiz(
+    req: HttpRequest,
+    body: Bytes,
+    library: Data<QuizLibrary>,
+    image_limits: Data<ImageLimits>,
+    address_limiter: Data<AddressLimiter>,
+) -> HttpResponse {
+    if let Some(address) = client_address(&req) {
+        if !address_limiter.check_create_game(address) {
+            return HttpResponse::TooManyRequests().json(json!({ "error": "You are doing that too fast" }));
+        }
+    }
+    let mut quiz = match decode(&body) {
+        Ok(quiz) => quiz,
+        Err(err) => return error_response(err.reason()),
+    };
+    let saved = web::block(move || {
+        image_limits.process_all(&mut quiz.questions).map_err(|err| err.reason())?;
+        library.save(&quiz).map_err(|err| err.reason())
+    }).await;
+    match saved {
+        Ok(Ok(code)) => HttpResponse::Ok().json(json!({ "code": code })),
+        Ok(Err(reason)) => error_response(reason),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Route handler for downloading a quiz from the library as a quiz file
+pub async fn export_quiz(
+    req: HttpRequest,
+    path: Path<String>,
+    library: Data<QuizLibrary>,
+    address_limiter: Data<AddressLimiter>,
+) -> HttpResponse {
+    if let Some(address) = client_address(&req) {
+        if !address_limiter.check_load_quiz(address) {
+            return HttpResponse::TooManyRequests().json(json!({ "error": "You are doing that too fast" }));
+        }
+    }
+    let code = path.into_inner();
+    match web::block(move || library.load(&code)).await {
+        Ok(Ok(quiz)) => file_response(&quiz),
+        Ok(Err(err)) => HttpResponse::NotFound().json(json!({ "error": err.reason() })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Route handler for downloading the quiz of a running game as a quiz file.
+/// Only the host of the game may download it because it contains the answers.
+pub async fn export_game(req: HttpRequest, path: Path<String>, manager: Data<Addr<GameManager>>) -> HttpResponse {
+    let session = Session::from_request(&req);
+    let exported = manager.send(ExportGame { id: path.into_inner(), session: session.id }).await;
+    match exported {
+        Ok(Ok(quiz)) => file_response(&quiz),
+        Ok(Err(reason)) => HttpResponse::Forbidden().json(json!({ "error": reason })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
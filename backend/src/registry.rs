@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use actix_web::{HttpResponse, web::{self, Data, Path}};
+use tracing::error;
+use serde_json::{json, Value};
+use crate::tools::{random_identifier, Identifier};
+
+/// A server process that games can be owned by. Clients are sent to the url
+/// of the node that owns a game when they ask another node for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub id: String,
+    pub url: String,
+}
+
+impl Node {
+    /// The node used when the registry is kept in memory for a single server
+    fn local() -> Node {
+        Node {
+            id: String::from("local"),
+            url: std::env::var("QUIZLER_NODE_URL").unwrap_or_default(),
+        }
+    }
+
+    /// Creates the node from QUIZLER_NODE_ID and QUIZLER_NODE_URL which must
+    /// both be set when the registry is shared. The id must stay the same
+    /// across restarts so that the node can release and restore its games.
+    fn from_env() -> io::Result<Node> {
+        Ok(Node {
+            id: required_var("QUIZLER_NODE_ID")?,
+            url: required_var("QUIZLER_NODE_URL")?,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "node": self.id, "url": self.url })
+    }
+
+    fn from_json(value: &Value) -> Option<Node> {
+        Some(Node {
+            id: value["node"].as_str()?.to_string(),
+            url: value["url"].as_str()?.to_string(),
+        })
+    }
+}
+
+/// Reads an environment variable that must be set when the registry is shared
+fn required_var(name: &str) -> io::Result<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} must be set when QUIZLER_REGISTRY_PATH is set", name),
+        ))
+}
+
+/// Storage mapping game codes to the node that owns them. Every node in a
+/// deployment must share the same registry so that codes are only ever
+/// claimed once and can be looked up from any node.
+pub trait GameRegistry: Debug + Send + Sync {
+    /// Claims the game code for the node. Returns false if the code is
+    /// already owned by any node.
+    fn claim(&self, game: &str, node: &Node) -> io::Result<bool>;
+
+    /// Releases the game code if it's owned by the node
+    fn release(&self, game: &str, node: &Node) -> io::Result<()>;
+
+    /// Finds the node that owns the game code
+    fn lookup(&self, game: &str) -> io::Result<Option<Node>>;
+
+    /// Releases every game code owned by the node. Used on startup to clear
+    /// codes left behind when the node last stopped.
+    fn release_node(&self, node: &Node) -> io::Result<()>;
+}
+
+/// Registry kept in memory used when the server runs as a single node
+#[derive(Debug, Default)]
+pub struct LocalRegistry {
+    games: Mutex<HashMap<Identifier, Node>>,
+}
+
+impl GameRegistry for LocalRegistry {
+    fn claim(&self, game: &str, node: &Node) -> io::Result<bool> {
+        let mut games = self.games.lock().unwrap();
+        if games.contains_key(game) {
+            return Ok(false);
+        }
+        games.insert(game.to_string(), node.clone());
+        Ok(true)
+    }
+
+    fn release(&self, game: &str, node: &Node) -> io::Result<()> {
+        let mut games = self.games.lock().unwrap();
+        if games.get(game).is_some_and(|owner| owner.id == node.id) {
+            games.remove(game);
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, game: &str) -> io::Result<Option<Node>> {
+        Ok(self.games.lock().unwrap().get(game).cloned())
+    }
+
+    fn release_node(&self, node: &Node) -> io::Result<()> {
+        self.games.lock().unwrap().retain(|_, owner| owner.id != node.id);
+        Ok(())
+    }
+}
+
+/// Registry stored in a directory shared between the nodes with a file per
+/// game code. Files are written in full under a temporary name and then
+/// linked to the code so only one node can claim each code and the file is
+/// never seen partially written. Suitable for nodes on the same host or sharing a network volume and
+/// as a local stand-in for external registries.
+#[derive(Debug)]
+pub struct FileRegistry {
+    path: PathBuf,
+}
+
+impl FileRegistry {
+    pub fn new(path: PathBuf) -> io::Result<FileRegistry> {
+        fs::create_dir_all(&path)?;
+        Ok(FileRegistry { path })
+    }
+
+    fn game_path(&self, game: &str) -> io::Result<PathBuf> {
+        // Codes come from clients so anything that isn't a code is rejected
+        // rather than being allowed to escape the directory
+        if game.is_empty() || !game.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid game code"));
+        }
+        Ok(self.path.join(game))
+    }
+
+    fn read(&self, path: &std::path::Path) -> io::Result<Option<Node>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).ok().as_ref().and_then(Node::from_json)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl GameRegistry for FileRegistry {
+    fn claim(&self, game: &str, node: &Node) -> io::Result<bool> {
+        let path = self.game_path(game)?;
+        // Temporary names can't be mistaken for codes as codes are alphanumeric
+        let temp = self.path.join(format!("{}.{}.tmp", game, random_identifier(8)));
+        let linked = fs::write(&temp, node.to_json().to_string())
+            .and_then(|_| fs::hard_link(&temp, &path));
+        let _ = fs::remove_file(&temp);
+        match linked {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn release(&self, game: &str, node: &Node) -> io::Result<()> {
+        let path = self.game_path(game)?;
+        if self.read(&path)?.is_some_and(|owner| owner.id == node.id) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, game: &str) -> io::Result<Option<Node>> {
+        match self.game_path(game) {
+            Ok(path) => self.read(&path),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn release_node(&self, node: &Node) -> io::Result<()> {
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                continue;
+            }
+            if self.read(&path)?.is_some_and(|owner| owner.id == node.id) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The registry used by this node along with the node itself
+#[derive(Debug, Clone)]
+pub struct Registry {
+    node: Node,
+    backend: Arc<dyn GameRegistry>,
+}
+
+impl Registry {
+    pub fn new(node: Node, backend: Arc<dyn GameRegistry>) -> Registry {
+        Registry { node, backend }
+    }
+
+    /// Creates the registry from the environment using a file registry in
+    /// QUIZLER_REGISTRY_PATH or an in memory registry if that isn't set
+    pub fn from_env() -> io::Result<Registry> {
+        match std::env::var("QUIZLER_REGISTRY_PATH") {
+            Ok(path) if !path.is_empty() => Ok(Registry::new(
+                Node::from_env()?,
+                Arc::new(FileRegistry::new(PathBuf::from(path))?),
+            )),
+            _ => Ok(Registry::new(Node::local(), Arc::new(LocalRegistry::default()))),
+        }
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Claims the game code for this node
+    pub fn claim(&self, game: &str) -> io::Result<bool> {
+        self.backend.claim(game, &self.node).inspect_err(|err| {
+            error!("Failed to claim game code {}: {}", game, err);
+        })
+    }
+
+    /// Claims a new random game code with the provided length for this node
+    pub fn claim_new(&self, length: usize) -> io::Result<Identifier> {
+        loop {
+            let game = random_identifier(length);
+            if self.claim(&game)? {
+                return Ok(game);
+            }
+        }
+    }
+
+    /// Releases the game code once the game has ended
+    pub fn release(&self, game: &str) {
+        if let Err(err) = self.backend.release(game, &self.node) {
+            error!("Failed to release game code {}: {}", game, err);
+        }
+    }
+
+    /// Releases all the codes that were owned by this node
+    pub fn reset(&self) {
+        if let Err(err) = self.backend.release_node(&self.node) {
+            error!("Failed to release game codes from the registry: {}", err);
+        }
+    }
+
+    /// Finds the node that owns the game code
+    pub fn lookup(&self, game: &str) -> Option<Node> {
+        self.backend.lookup(game).unwrap_or_else(|err| {
+            error!("Failed to look up game code {}: {}", game, err);
+            None
+        })
+    }
+
+    /// Finds the node that owns the game code if it's owned by another node
+    pub fn remote(&self, game: &str) -> Option<Node> {
+        self.lookup(game).filter(|owner| owner.id != self.node.id)
+    }
+}
+
+/// Route handler for finding the node that owns a game. Used by proxies to
+/// route clients to the right node.
+pub async fn locate_game(id: Path<Identifier>, registry: Data<Registry>) -> HttpResponse {
+    let id = id.into_inner().to_ascii_uppercase();
+    match web::block(move || registry.lookup(&id)).await {
+        Ok(Some(node)) => HttpResponse::Ok().json(node.to_json()),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str) -> Node {
+        Node { id: String::from(id), url: format!("http://{}", id) }
+    }
+
+    /// A file registry in its own directory which is removed once dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> TestDir {
+            TestDir(std::env::temp_dir().join(format!("quizler-registry-{}", random_identifier(12))))
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn claim_and_release(registry: &dyn GameRegistry) {
+        let (a, b) = (node("a"), node("b"));
+        assert!(registry.claim("ABCDE", &a).unwrap());
+        assert!(!registry.claim("ABCDE", &a).unwrap());
+        assert!(!registry.claim("ABCDE", &b).unwrap());
+        assert_eq!(registry.lookup("ABCDE").unwrap(), Some(a.clone()));
+
+        // Only the owner can release a code
+        registry.release("ABCDE", &b).unwrap();
+        assert_eq!(registry.lookup("ABCDE").unwrap(), Some(a.clone()));
+        registry.release("ABCDE", &a).unwrap();
+        assert_eq!(registry.lookup("ABCDE").unwrap(), None);
+        assert!(registry.claim("ABCDE", &b).unwrap());
+    }
+
+    fn release_node(registry: &dyn GameRegistry) {
+        let (a, b) = (node("a"), node("b"));
+        assert!(registry.claim("AAAAA", &a).unwrap());
+        assert!(registry.claim("AAAAB", &a).unwrap());
+        assert!(registry.claim("BBBBB", &b).unwrap());
+        registry.release_node(&a).unwrap();
+        assert_eq!(registry.lookup("AAAAA").unwrap(), None);
+        assert_eq!(registry.lookup("AAAAB").unwrap(), None);
+        assert_eq!(registry.lookup("BBBBB").unwrap(), Some(b));
+    }
+
+    #[test]
+    fn local_claim_and_release() {
+        claim_and_release(&LocalRegistry::default());
+    }
+
+    #[test]
+    fn local_release_node() {
+        release_node(&LocalRegistry::default());
+    }
+
+    #[test]
+    fn file_claim_and_release() {
+        let dir = TestDir::new();
+        claim_and_release(&FileRegistry::new(dir.0.clone()).unwrap());
+    }
+
+    #[test]
+    fn file_release_node() {
+        let dir = TestDir::new();
+        release_node(&FileRegistry::new(dir.0.clone()).unwrap());
+    }
+
+    #[test]
+    fn file_shared_between_nodes() {
+        let dir = TestDir::new();
+        let first = FileRegistry::new(dir.0.clone()).unwrap();
+        let second = FileRegistry::new(dir.0.clone()).unwrap();
+        assert!(first.claim("ABCDE", &node("a")).unwrap());
+        assert!(!second.claim("ABCDE", &node("b")).unwrap());
+        assert_eq!(second.lookup("ABCDE").unwrap(), Some(node("a")));
+        // Temporary files are removed once the code is claimed
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn file_rejects_invalid_codes() {
+        let dir = TestDir::new();
+        let registry = FileRegistry::new(dir.0.clone()).unwrap();
+        assert!(registry.claim("../escape", &node("a")).is_err());
+        assert!(registry.claim("", &node("a")).is_err());
+        assert_eq!(registry.lookup("../escape").unwrap(), None);
+    }
+
+    #[test]
+    fn file_claim_concurrently() {
+        let dir = TestDir::new();
+        let registry = Arc::new(FileRegistry::new(dir.0.clone()).unwrap());
+        let claimed: Vec<bool> = (0..8)
+            .map(|i| {
+                let registry = registry.clone();
+                std::thread::spawn(move || registry.claim("ABCDE", &node(&i.to_string())).unwrap())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(claimed.iter().filter(|claimed| **claimed).count(), 1);
+        assert!(registry.lookup("ABCDE").unwrap().is_some());
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{HttpRequest, HttpResponse, web::{self, Data, Path, Query}};
+use tracing::{error, info};
+use serde_json::{json, Value};
+use crate::packets::ResultsFormat;
+use crate::socket::Session;
+use crate::tools::Identifier;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultsError {
+    NotFound,
+    Storage,
+    Corrupted,
+}
+
+impl ResultsError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ResultsError::NotFound => "There are no results for that game",
+            ResultsError::Storage => "Failed to access the game results",
+            ResultsError::Corrupted => "Those game results could not be loaded",
+        }
+    }
+}
+
+impl From<io::Error> for ResultsError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::NotFound {
+            ResultsError::NotFound
+        } else {
+            error!("Game results IO error: {}", err);
+            ResultsError::Storage
+        }
+    }
+}
+
+/// The answer a player gave to a single question
+#[derive(Debug, Clone)]
+pub struct AnswerRecord {
+    pub answer: u8,
+    pub correct: bool,
+    /// The time in milliseconds the player took to answer
+    pub time: u32,
+    pub points: u32,
+}
+
+impl AnswerRecord {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "answer": self.answer,
+            "correct": self.correct,
+            "time": self.time,
+            "points": self.points,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<AnswerRecord> {
+        let number = |value: &Value| value.as_u64().and_then(|value| u32::try_from(value).ok());
+        Some(AnswerRecord {
+            answer: u8::try_from(value["answer"].as_u64()?).ok()?,
+            correct: value["correct"].as_bool()?,
+            time: number(&value["time"])?,
+            points: number(&value["points"])?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayerResult {
+    pub id: Identifier,
+    pub name: String,
+    pub score: u32,
+    /// The answers to each question, None if the player didn't answer
+    pub answers: Vec<Option<AnswerRecord>>,
+}
+
+impl PlayerResult {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "score": self.score,
+            "answers": self.answers.iter()
+                .map(|answer| answer.as_ref().map(AnswerRecord::to_json))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<PlayerResult> {
+        let mut answers = Vec::new();
+        for answer in value["answers"].as_array()? {
+            answers.push(if answer.is_null() {
+                None
+            } else {
+                Some(AnswerRecord::from_json(answer)?)
+            });
+        }
+        Some(PlayerResult {
+            id: value["id"].as_str()?.to_string(),
+            name: value["name"].as_str()?.to_string(),
+            score: u32::try_from(value["score"].as_u64()?).ok()?,
+            answers,
+        })
+    }
+}
+
+/// The results of a finished game used for grading
+#[derive(Debug, Clone)]
+pub struct GameResults {
+    pub game_id: Identifier,
+    pub host_session: Identifier,
+    pub title: String,
+    /// The unix timestamp in seconds of when the game finished
+    pub finished_at: u64,
+    pub questions: Vec<String>,
+    pub players: Vec<PlayerResult>,
+}
+
+/// Escapes a value for use as a CSV field. Values that spreadsheets would
+/// treat as a formula are prefixed with a quote so they're shown as text.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        String::from(value)
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+impl GameResults {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// Creates the JSON report of these results. The host session is only
+    /// included when the results are being stored.
+    fn to_json(&self, include_session: bool) -> Value {
+        let players: Vec<Value> = self.players.iter()
+            .map(PlayerResult::to_json)
+            .collect();
+        let mut value = json!({
+            "game_id": self.game_id,
+            "title": self.title,
+            "finished_at": self.finished_at,
+            "questions": self.questions,
+            "players": players,
+        });
+        if include_session {
+            value["host_session"] = Value::from(self.host_session.clone());
+        }
+        value
+    }
+
+    fn from_json(value: &Value) -> Option<GameResults> {
+        let string = |value: &Value| value.as_str().map(String::from);
+        let players = value["players"].as_array()?
+            .iter()
+            .map(PlayerResult::from_json)
+            .collect::<Option<_>>()?;
+        Some(GameResults {
+            game_id: string(&value["game_id"])?,
+            host_session: string(&value["host_session"])?,
+            title: string(&value["title"])?,
+            finished_at: value["finished_at"].as_u64()?,
+            questions: value["questions"].as_array()?.iter().map(string).collect::<Option<_>>()?,
+            players,
+        })
+    }
+
+    /// Creates a CSV report with a row for each player containing their
+    /// score followed by their answer, whether it was correct and their
+    /// response time for each question.
+    pub fn to_csv(&self) -> String {
+        let mut header = vec![String::from("Player"), String::from("Score")];
+        for (index, question) in self.questions.iter().enumerate() {
+            let name = format!("Q{} {}", index + 1, question);
+            header.push(format!("{} (Answer)", name));
+            header.push(format!("{} (Correct)", name));
+            header.push(format!("{} (Time ms)", name));
+        }
+        let mut out = header.iter().map(|value| csv_field(value)).collect::<Vec<_>>().join(",");
+        out.push('\n');
+        for player in &self.players {
+            let mut row = vec![csv_field(&player.name), player.score.to_string()];
+            for answer in &player.answers {
+                match answer {
+                    None => row.extend([String::new(), String::new(), String::new()]),
+                    Some(answer) => row.extend([
+                        (answer.answer as u32 + 1).to_string(),
+                        answer.correct.to_string(),
+                        answer.time.to_string(),
+                    ]),
+                }
+            }
+            out.push_str(&row.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn export(&self, format: &ResultsFormat) -> String {
+        match format {
+            ResultsFormat::Json => self.to_json(false).to_string(),
+            ResultsFormat::Csv => self.to_csv(),
+        }
+    }
+}
+
+/// The result files of each game code
+type ResultsIndex = HashMap<Identifier, Vec<PathBuf>>;
+
+/// File based storage for the results of finished games. Results are stored
+/// as JSON files named after the game and the time it finished because game
+/// codes are reused.
+#[derive(Debug, Clone)]
+pub struct ResultsStore {
+    root: PathBuf,
+    /// The files in the results directory grouped by game code so they don't
+    /// have to be listed for every lookup. Created by the first lookup.
+    index: Arc<Mutex<Option<ResultsIndex>>>,
+}
+
+impl ResultsStore {
+    pub fn new(root: PathBuf) -> ResultsStore {
+        ResultsStore { root, index: Arc::default() }
+    }
+
+    pub fn from_env() -> ResultsStore {
+        let root = std::env::var("QUIZLER_RESULTS_PATH").unwrap_or_else(|_| String::from("results"));
+        ResultsStore::new(PathBuf::from(root))
+    }
+
+    pub fn save(&self, results: &GameResults) -> Result<(), ResultsError> {
+        fs::create_dir_all(&self.root)?;
+        let path = self.root.join(format!("{}_{}.json", results.game_id, results.finished_at));
+        fs::write(&path, results.to_json(true).to_string())?;
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = index.as_mut() {
+            let files = index.entry(results.game_id.to_ascii_uppercase()).or_default();
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        info!("Saved results of game {} ({})", results.title, results.game_id);
+        Ok(())
+    }
+
+    /// Lists the result files in the results directory by game code
+    fn load_index(&self) -> Result<ResultsIndex, ResultsError> {
+        let mut index = ResultsIndex::new();
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(index),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if let Some((game_id, _)) = name.strip_suffix(".json").and_then(|name| name.split_once('_')) {
+                index.entry(game_id.to_ascii_uppercase()).or_default().push(path);
+            }
+        }
+        Ok(index)
+    }
+
+    /// The result files of the game with the provided code
+    fn game_files(&self, game_id: &str) -> Result<Vec<PathBuf>, ResultsError> {
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        if index.is_none() {
+            *index = Some(self.load_index()?);
+        }
+        Ok(index.as_ref()
+            .and_then(|index| index.get(&game_id.to_ascii_uppercase()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Finds the most recent results of the game with the provided id that
+    /// was hosted by the provided session. Files that can't be loaded are
+    /// skipped so they don't hide the other results of the game.
+    pub fn find(&self, game_id: &str, host_session: &str) -> Result<GameResults, ResultsError> {
+        let mut found: Option<GameResults> = None;
+        let mut corrupted = false;
+        for path in self.game_files(game_id)? {
+            let results = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok())
+                .and_then(|value| GameResults::from_json(&value));
+            let Some(results) = results else {
+                error!("Failed to load game results from {}", path.display());
+                corrupted = true;
+                continue;
+            };
+            if results.host_session == host_session
+                && found.as_ref().is_none_or(|found| found.finished_at < results.finished_at) {
+                found = Some(results);
+            }
+        }
+        match found {
+            Some(results) => Ok(results),
+            None if corrupted => Err(ResultsError::Corrupted),
+            None => Err(ResultsError::NotFound),
+        }
+    }
+}
+
+/// Route handler for downloading the results of a finished game as either
+/// CSV or JSON. Only the host of the game may download its results.
+pub async fn serve_results(
+    req: HttpRequest,
+    path: Path<String>,
+    query: Query<HashMap<String, String>>,
+    store: Data<ResultsStore>,
+) -> HttpResponse {
+    let format = match query.get("format").map(String::as_str) {
+        None | Some("json") => ResultsFormat::Json,
+        Some("csv") => ResultsFormat::Csv,
+        Some(_) => return HttpResponse::BadRequest().json(json!({ "error": "Unknown results format" })),
+    };
+    let session = Session::from_request(&req);
+    let game_id = path.into_inner();
+    match web::block(move || store.find(&game_id, &session.id)).await {
+        Ok(Ok(results)) => {
+            let (content_type, extension) = match format {
+                ResultsFormat::Json => ("application/json", "json"),
+                ResultsFormat::Csv => ("text/csv", "csv"),
+            };
+            HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header(("Content-Disposition", format!(
+                    "attachment; filename=\"results-{}-{}.{}\"",
+                    results.game_id, results.finished_at, extension
+                )))
+                .body(results.export(&format))
+        }
+        Ok(Err(err)) => HttpResponse::NotFound().json(json!({ "error": err.reason() })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::random_identifier;
+
+    /// A results directory which is removed once dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> TestDir {
+            TestDir(std::env::temp_dir().join(format!("quizler-results-{}", random_identifier(12))))
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn results(finished_at: u64) -> GameResults {
+        GameResults {
+            game_id: String::from("A1B2C"),
+            host_session: String::from("0123456789ABCDEF"),
+            title: String::from("Capitals"),
+            finished_at,
+            questions: vec![String::from("Capital of France?"), String::from("Capital of Italy?")],
+            players: vec![PlayerResult {
+                id: String::from("D3E4"),
+                name: String::from("=HYPERLINK(\"x\")"),
+                score: 150,
+                answers: vec![
+                    Some(AnswerRecord { answer: 0, correct: true, time: 1200, points: 150 }),
+                    None,
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_escaped() {
+        assert_eq!(csv_field("Bob"), "Bob");
+        assert_eq!(csv_field("Smith, Bob"), "\"Smith, Bob\"");
+        assert_eq!(csv_field("Bob \"The Builder\""), "\"Bob \"\"The Builder\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn csv_formulas_are_shown_as_text() {
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("\tcmd"), "'\tcmd");
+        assert_eq!(csv_field("\rcmd"), "\"'\rcmd\"");
+        assert_eq!(csv_field("=SUM(A1,B1)"), "\"'=SUM(A1,B1)\"");
+        assert_eq!(csv_field("1+1="), "1+1=");
+    }
+
+    #[test]
+    fn csv_report() {
+        assert_eq!(
+            results(100).to_csv(),
+            "Player,Score,\
+            Q1 Capital of France? (Answer),Q1 Capital of France? (Correct),Q1 Capital of France? (Time ms),\
+            Q2 Capital of Italy? (Answer),Q2 Capital of Italy? (Correct),Q2 Capital of Italy? (Time ms)\n\
+            \"'=HYPERLINK(\"\"x\"\")\",150,1,true,1200,,,\n"
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let results = results(100);
+        let value = results.to_json(true);
+        let loaded = GameResults::from_json(&value).unwrap();
+        assert_eq!(loaded.to_json(true), value);
+        assert_eq!(loaded.players[0].answers[0].as_ref().map(|answer| answer.time), Some(1200));
+        assert!(loaded.players[0].answers[1].is_none());
+        // Exported results don't include the host session so can't be loaded
+        let exported = results.to_json(false);
+        assert!(exported.get("host_session").is_none());
+        assert!(GameResults::from_json(&exported).is_none());
+    }
+
+    #[test]
+    fn invalid_answers_are_rejected() {
+        assert!(AnswerRecord::from_json(&json!({ "answer": 256, "correct": true, "time": 1, "points": 1 })).is_none());
+        assert!(AnswerRecord::from_json(&json!({ "answer": 1, "correct": "yes", "time": 1, "points": 1 })).is_none());
+    }
+
+    #[test]
+    fn finds_the_latest_results_of_the_host() {
+        let dir = TestDir::new();
+        let store = ResultsStore::new(dir.0.clone());
+        store.save(&results(100)).unwrap();
+        store.save(&results(200)).unwrap();
+        fs::write(dir.0.join("A1B2C_300.json"), "{ not json").unwrap();
+        assert_eq!(store.find("a1b2c", "0123456789ABCDEF").unwrap().finished_at, 200);
+        assert_eq!(store.find("A1B2C", "FEDCBA9876543210").unwrap_err(), ResultsError::Corrupted);
+        assert_eq!(store.find("Z9Y8X", "0123456789ABCDEF").unwrap_err(), ResultsError::NotFound);
+
+        // A store which hasn't indexed the directory yet finds the same results
+        let store = ResultsStore::new(dir.0.clone());
+        assert_eq!(store.find("A1B2C", "0123456789ABCDEF").unwrap().finished_at, 200);
+    }
+}
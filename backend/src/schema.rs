@@ -0,0 +1,91 @@
+use std::fmt::Write;
+use serde_json::{json, Value};
+use crate::packets::{data_schema, MIN_PROTOCOL_VERSION, packets_schema, PROTOCOL_VERSION};
+
+/// Creates a machine-readable description of the packet protocol generated
+/// from the packet definitions so client bindings can be checked for drift.
+pub fn schema() -> Value {
+    json!({
+        "version": PROTOCOL_VERSION,
+        "min_version": MIN_PROTOCOL_VERSION,
+        "types": data_schema(),
+        "groups": packets_schema(),
+    })
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> &'a str {
+    value[key].as_str().unwrap_or_default()
+}
+
+fn write_fields(out: &mut String, fields: &Value) {
+    let fields = fields.as_array().map(Vec::as_slice).unwrap_or_default();
+    if fields.is_empty() {
+        out.push_str("This packet has no fields\n\n");
+        return;
+    }
+    out.push_str("| Field | Type |\n|-------|------|\n");
+    for field in fields {
+        let _ = writeln!(out, "| {} | `{}` |", str_field(field, "name"), str_field(field, "type"));
+    }
+    out.push('\n');
+}
+
+/// Renders the protocol schema as a markdown document
+pub fn markdown() -> String {
+    let schema = schema();
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "# Packets\n\n\
+        > This file is generated by running `quizler schema markdown`, do not edit it by hand\n\n\
+        Protocol version **{}** (oldest supported version **{}**)\n\n\
+        Clientbound packets are sent from the server to the client and serverbound packets are\n\
+        sent from the client to the server. Every packet starts with its id encoded as a VarInt\n\
+        followed by its fields in the order listed. `Blob` fields are encoded as a `Vec<u8>` in the\n\
+        binary protocol and as a base64 string in the JSON protocol.\n\n## Types\n\n",
+        PROTOCOL_VERSION,
+        MIN_PROTOCOL_VERSION
+    );
+    for data in schema["types"].as_array().into_iter().flatten() {
+        let _ = writeln!(out, "### {} ({})\n", str_field(data, "name"), str_field(data, "direction"));
+        if str_field(data, "kind") == "enum" {
+            let _ = write!(out, "Enum encoded as `{}`\n\n| Name | Value |\n|------|-------|\n", str_field(data, "repr"));
+            for value in data["values"].as_array().into_iter().flatten() {
+                let _ = writeln!(out, "| {} | {} |", str_field(value, "name"), value["value"]);
+            }
+            out.push('\n');
+        } else {
+            write_fields(&mut out, &data["fields"]);
+        }
+    }
+    for group in schema["groups"].as_array().into_iter().flatten() {
+        let _ = writeln!(out, "## {} ({})\n", str_field(group, "name"), str_field(group, "direction"));
+        for packet in group["packets"].as_array().into_iter().flatten() {
+            let id = packet["id"].as_u64().unwrap_or_default();
+            let _ = writeln!(out, "### 0x{:02X} {}\n", id, str_field(packet, "name"));
+            write_fields(&mut out, &packet["fields"]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_document_is_up_to_date() {
+        assert!(
+            markdown() == include_str!("../packets.md"),
+            "packets.md is out of date, regenerate it with `cargo run -- schema markdown > packets.md`"
+        );
+    }
+
+    #[test]
+    fn type_names_are_spelled_out() {
+        let groups = schema()["groups"].to_string();
+        assert!(groups.contains(r#"{"name":"scores","type":"HashMap<String, u32>"}"#));
+        assert!(groups.contains(r#"{"name":"questions","type":"Vec<QuestionData>"}"#));
+        assert!(groups.contains(r#"{"name":"data","type":"Blob"}"#));
+    }
+}
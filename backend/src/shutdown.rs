@@ -0,0 +1,167 @@
+use std::future::{Future, poll_fn};
+use std::io;
+use std::pin::pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use actix::{ActorContext, ActorFutureExt, Addr, Handler, Message, MessageResult, ResponseActFuture, WrapFuture};
+use actix::fut::ready;
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use actix_web::rt::{signal, time::sleep};
+use tracing::{error, info};
+use crate::audit::{AuditAction, AuditEvent, SERVER_ACTOR};
+use serde_json::Value;
+use crate::game::{Game, GameManager, Shutdown};
+use crate::snapshot::game_to_json;
+use crate::packets::GameState;
+use crate::tools::{env_value, Identifier};
+
+/// The reason given to everyone still in a game when the server stops
+const SHUTDOWN_REASON: &str = "The server is restarting. Please rejoin shortly.";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Handler<Shutdown> for GameManager {
+    type Result = ResponseActFuture<Self, usize>;
+
+    fn handle(&mut self, msg: Shutdown, _ctx: &mut Self::Context) -> Self::Result {
+        self.shutting_down = true;
+        let games: Vec<Addr<Game>> = self.games.values().cloned().collect();
+        let snapshots = self.snapshots.clone();
+        let ids: Vec<Identifier> = self.games.keys().cloned().collect();
+        let registry = self.registry.clone();
+        Box::pin(async move {
+            if snapshots.is_enabled() {
+                // Games carry on after the restart so clients are left to reconnect
+                let mut snapshot = Vec::with_capacity(games.len());
+                for game in &games {
+                    if let Ok(value) = game.send(TakeSnapshot).await {
+                        snapshot.push(value);
+                    }
+                }
+                match web::block(move || snapshots.save(snapshot)).await.unwrap_or_else(|err| Err(io::Error::other(err))) {
+                    Ok(()) => {
+                        games.iter().for_each(|game| game.do_send(StopGame));
+                        // Codes are claimed again by whichever node restores them
+                        let _ = web::block(move || ids.iter().for_each(|id| registry.release(id))).await;
+                        return None;
+                    }
+                    Err(err) => error!("Failed to save game snapshot: {}", err),
+                }
+            }
+            let mut running = 0;
+            for game in &games {
+                running += game.send(msg).await.unwrap_or(0);
+            }
+            Some(running)
+        }.into_actor(self).map(|running, act, _ctx| match running {
+            Some(running) => running,
+            None => {
+                act.games.clear();
+                0
+            }
+        }))
+    }
+}
+
+impl Handler<Shutdown> for Game {
+    type Result = ResponseActFuture<Self, usize>;
+
+    fn handle(&mut self, msg: Shutdown, _ctx: &mut Self::Context) -> Self::Result {
+        let running = matches!(self.state, GameState::Starting | GameState::Started);
+        if running && !msg.force {
+            return Box::pin(ready(1));
+        }
+        let results = running.then(|| self.stop());
+        self.end(SHUTDOWN_REASON);
+        self.services.audit.record(AuditEvent::new(&self.id, SERVER_ACTOR, AuditAction::GameEnded).detail("server shutdown"));
+        // The server waits for the results to be saved before it stops
+        let save = results.map(|results| self.save_results(results));
+        Box::pin(async move {
+            if let Some(save) = save {
+                save.await;
+            }
+        }.into_actor(self).map(|_, _act, ctx| {
+            ctx.stop();
+            0
+        }))
+    }
+}
+
+/// Requests a snapshot of a game so that it can be restored after the server
+/// restarts
+#[derive(Message)]
+#[rtype(result = "Value")]
+struct TakeSnapshot;
+
+impl Handler<TakeSnapshot> for Game {
+    type Result = MessageResult<TakeSnapshot>;
+
+    fn handle(&mut self, _msg: TakeSnapshot, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(game_to_json(self))
+    }
+}
+
+/// Stops a game that has been saved to a snapshot without ending it for the
+/// host and players
+#[derive(Message)]
+#[rtype(result = "()")]
+struct StopGame;
+
+impl Handler<StopGame> for Game {
+    type Result = ();
+
+    fn handle(&mut self, _msg: StopGame, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+/// Waits for the process to be asked to stop by either SIGTERM or Ctrl+C
+async fn stop_signal() {
+    let mut ctrl_c = pin!(signal::ctrl_c());
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                poll_fn(|cx| {
+                    if ctrl_c.as_mut().poll(cx).is_ready() || terminate.poll_recv(cx).is_ready() {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                }).await;
+                return;
+            }
+            Err(err) => error!("Failed to listen for SIGTERM: {}", err),
+        }
+    }
+    let _ = ctrl_c.await;
+}
+
+/// Waits for a stop signal and then shuts down the server. Games that are in
+/// progress are given until the QUIZLER_SHUTDOWN_TIMEOUT (in seconds) to
+/// finish before everyone still connected is told the server is restarting
+/// and the results of unfinished games are saved.
+pub async fn shutdown_on_signal(server: ServerHandle, manager: Addr<GameManager>) {
+    stop_signal().await;
+    let timeout = Duration::from_secs(env_value("QUIZLER_SHUTDOWN_TIMEOUT", 0));
+    let deadline = Instant::now() + timeout;
+    info!("Shutting down, waiting up to {}s for games to finish", timeout.as_secs());
+    loop {
+        let force = Instant::now() >= deadline;
+        match manager.send(Shutdown { force }).await {
+            Ok(0) => break,
+            Ok(running) if !force => {
+                info!("Waiting for {} game(s) to finish", running);
+                sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()))).await;
+            }
+            Ok(_) => break,
+            Err(err) => {
+                error!("Failed to shut down games: {}", err);
+                break;
+            }
+        }
+    }
+    info!("Stopping server");
+    server.stop(true).await;
+}
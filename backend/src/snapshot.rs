@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use tracing::{error, info};
+use serde_json::{json, Map, Value};
+use crate::game::{Ban, ClientAddr, Game, GameServices, GameTimer, Player, Question, QuestionIndex};
+use crate::images::ImageStore;
+use crate::json::JsonValue;
+use crate::packets::{GameState, QuestionData};
+use crate::results::{AnswerRecord, PlayerResult};
+use crate::socket::Session;
+use crate::tools::Identifier;
+
+/// The version of the snapshot format. Snapshots made by other versions are
+/// ignored because they only need to survive a single restart.
+const SNAPSHOT_VERSION: u64 = 2;
+
+/// Stores the games that were running when the server shut down so that they
+/// can be restored when it starts again. Hosts and players are detached from
+/// restored games until they resume them. Disabled unless a path is set.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotStore {
+    path: Option<PathBuf>,
+}
+
+fn session_to_json(session: &Session) -> Value {
+    json!({
+        "id": session.id,
+        "address": session.address.map(|address| address.to_string()),
+    })
+}
+
+fn session_from_json(value: &Value) -> Option<Session> {
+    Some(Session {
+        id: value["id"].as_str()?.to_string(),
+        address: value["address"].as_str().and_then(|address| address.parse().ok()),
+    })
+}
+
+fn millis(value: &Value) -> Option<Duration> {
+    value.as_u64().map(Duration::from_millis)
+}
+
+fn question_to_json(game: &Game, question: &Question) -> Value {
+    let mut value = question.data.to_json();
+    value["image"] = Value::from(question.image.as_ref()
+        .and_then(|hash| game.services.images.get(&game.id, hash))
+        .map(|image| STANDARD.encode(&image.data))
+        .unwrap_or_default());
+    value
+}
+
+fn question_from_json(id: &Identifier, value: &Value, images: &ImageStore) -> Option<Question> {
+    let image = STANDARD.decode(value["image"].as_str()?).ok()?;
+    let mut data = value.clone();
+    data["image"] = Value::from("");
+    let data = QuestionData::from_json(&data).ok()?;
+    Some(Question {
+        image: if image.is_empty() {
+            None
+        } else {
+            Some(images.insert(id, data.image_type.clone(), image))
+        },
+        data,
+        start_time: Instant::now(),
+    })
+}
+
+fn player_to_json(player: &Player) -> Value {
+    let answers: Map<String, Value> = player.answers.iter()
+        .map(|(index, answer)| (index.to_string(), answer.to_json()))
+        .collect();
+    json!({
+        "id": player.id,
+        "name": player.name,
+        "score": player.score,
+        "session": session_to_json(&player.session),
+        "answers": answers,
+    })
+}
+
+fn player_from_json(value: &Value) -> Option<Player> {
+    let mut answers = HashMap::new();
+    for (index, answer) in value["answers"].as_object()? {
+        answers.insert(index.parse::<QuestionIndex>().ok()?, AnswerRecord::from_json(answer)?);
+    }
+    Some(Player {
+        id: value["id"].as_str()?.to_string(),
+        name: value["name"].as_str()?.to_string(),
+        score: u32::try_from(value["score"].as_u64()?).ok()?,
+        answers,
+        answer_time: None,
+        ret: ClientAddr::default(),
+        session: session_from_json(&value["session"])?,
+    })
+}
+
+/// Creates the snapshot of a game including its images
+pub(crate) fn game_to_json(game: &Game) -> Value {
+    let players: Vec<Value> = game.players.values()
+        .map(player_to_json)
+        .collect();
+    json!({
+        "id": game.id,
+        "title": game.title,
+        "host_session": game.host_session,
+        "state": game.state.to_json(),
+        "question_index": game.question_index,
+        "marking": game.marking,
+        "timer": {
+            "total": game.timer.duration.as_millis() as u64,
+            "remaining": game.timer.time_left().as_millis() as u64,
+            "running": game.timer.need_sync,
+        },
+        "questions": game.questions.iter()
+            .map(|question| question_to_json(game, question))
+            .collect::<Vec<_>>(),
+        "players": players,
+        "bans": game.bans.iter()
+            .map(|ban| json!({ "id": ban.id, "name": ban.name, "session": session_to_json(&ban.session) }))
+            .collect::<Vec<_>>(),
+        "departed": game.departed.iter()
+            .map(PlayerResult::to_json)
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn game_from_json(value: &Value, services: &GameServices) -> Option<Game> {
+    let id = value["id"].as_str()?.to_string();
+    let host_session = value["host_session"].as_str()?.to_string();
+    let state = GameState::from_json(&value["state"]).ok()?;
+    let mut questions = Vec::new();
+    for question in value["questions"].as_array()? {
+        questions.push(question_from_json(&id, question, &services.images)?);
+    }
+    let mut players = HashMap::new();
+    for player in value["players"].as_array()? {
+        let player = player_from_json(player)?;
+        players.insert(player.id.clone(), player);
+    }
+    let mut bans = Vec::new();
+    for ban in value["bans"].as_array()? {
+        bans.push(Ban {
+            id: ban["id"].as_str()?.to_string(),
+            name: ban["name"].as_str()?.to_string(),
+            session: session_from_json(&ban["session"])?,
+        });
+    }
+
+    let departed = value["departed"].as_array()?
+        .iter()
+        .map(PlayerResult::from_json)
+        .collect::<Option<_>>()?;
+
+    // The timer carries on from where it was when the snapshot was taken
+    let total = millis(&value["timer"]["total"])?;
+    let elapsed = total.saturating_sub(millis(&value["timer"]["remaining"])?);
+    let mut timer = GameTimer::new();
+    timer.duration = total;
+    timer.start = Instant::now().checked_sub(elapsed).unwrap_or_else(Instant::now);
+    timer.elapsed = elapsed;
+    timer.need_sync = value["timer"]["running"].as_bool()?;
+    // Nothing happens until the host resumes the game
+    timer.pause();
+
+    let question_index = usize::try_from(value["question_index"].as_u64()?).ok()?;
+    let marking = value["marking"].as_bool()?;
+    if state == GameState::Started && !marking {
+        questions.get_mut(question_index)?.start_time = timer.start;
+    }
+    Some(Game {
+        host: ClientAddr::default(),
+        results: if state == GameState::Stopped { services.results.find(&id, &host_session).ok() } else { None },
+        host_session,
+        id,
+        title: value["title"].as_str()?.to_string(),
+        questions,
+        players,
+        bans,
+        departed,
+        state,
+        timer,
+        question_index,
+        marking,
+        restored: Some(Instant::now()),
+        services: services.clone(),
+    })
+}
+
+impl SnapshotStore {
+    pub fn from_env() -> SnapshotStore {
+        SnapshotStore {
+            path: std::env::var("QUIZLER_SNAPSHOT_PATH")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Writes a snapshot containing the provided game snapshots
+    pub fn save(&self, games: Vec<Value>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let contents = json!({
+            "version": SNAPSHOT_VERSION,
+            "games": games,
+        });
+        let count = contents["games"].as_array().map_or(0, Vec::len);
+        let contents = contents.to_string();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, path)?;
+        info!("Saved snapshot of {} game(s)", count);
+        Ok(())
+    }
+
+    /// Loads the games from the snapshot if there is one. The snapshot is
+    /// removed once it's loaded so that the same games aren't restored again.
+    pub fn restore(&self, services: &GameServices) -> Vec<Game> {
+        let mut games = Vec::new();
+        let Some(path) = &self.path else {
+            return games;
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return games,
+            Err(err) => {
+                error!("Failed to read game snapshot: {}", err);
+                return games;
+            }
+        };
+        if let Err(err) = fs::remove_file(path) {
+            error!("Failed to remove game snapshot: {}", err);
+        }
+        let value: Value = match serde_json::from_str(&contents) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Game snapshot is corrupted: {}", err);
+                return games;
+            }
+        };
+        if value["version"].as_u64() != Some(SNAPSHOT_VERSION) {
+            error!("Ignoring game snapshot made by a different version");
+            return games;
+        }
+        for game in value["games"].as_array().map(Vec::as_slice).unwrap_or_default() {
+            match game_from_json(game, services) {
+                Some(game) => {
+                    info!("Restored game {} ({}) from snapshot", game.title, game.id);
+                    games.push(game);
+                }
+                None => {
+                    error!("Skipping invalid game in snapshot");
+                    // Images may have been stored before the game was found to be invalid
+                    if let Some(id) = game["id"].as_str() {
+                        services.images.remove_game(id);
+                    }
+                }
+            }
+        }
+        games
+    }
+}
@@ -1,27 +1,128 @@
-use std::io::Cursor;
+use std::cell::Cell;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use actix::*;
+use actix::prelude::Stream;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::HttpRequest;
 use actix_web_actors::ws;
-use wsbps::{Readable, Writable};
-use crate::game::{ClientAction, GameManager, ServerAction};
-use crate::packets::{ClientPackets, GameState, ServerPackets, StateChange};
-use crate::tools::Identifier;
-use log::{error, info, warn, debug};
-use fut::{ready, Ready};
+use wsbps::{VarInt, Writable};
+use crate::game::{ClientAction, Game, GameAction, GameManager, ServerAction};
+use crate::limits::{AddressLimiter, ConnectionLimiter, LimitClass, LimitResult, TokenBucket};
+use crate::compression::{compress, decompress};
+use crate::json;
+use crate::logging::LogContext;
+use crate::metrics::Metrics;
+use crate::packets::{Blob, ClientPackets, decode_client_packet, DecodeError, GameState, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, ServerPackets, StateChange};
+use crate::tools::{client_address, Identifier, random_identifier, session_hash};
+use tracing::{error, info, warn, debug};
+use fut::ready;
+
+/// Counter used to give each connection a unique id for logging
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
 
 pub struct Connection {
+    /// Unique id of this connection used for logging
+    pub id: u64,
     pub game_data: GameData,
+    /// The game that this connection is hosting or playing in
+    pub game: Option<Addr<Game>>,
     pub manager: Addr<GameManager>,
+    pub session: Session,
+    limiter: ConnectionLimiter,
+    address_limiter: AddressLimiter,
+    metrics: Metrics,
+    /// Allowance of malformed packets which recovers over time so that only
+    /// clients repeatedly sending them are disconnected
+    malformed_packets: TokenBucket,
+    /// When data was last received from the client, shared with the stream
+    /// of the request payload so that partially received frames count
+    heartbeat: Rc<Cell<Instant>>,
+    protocol_version: Option<u32>,
+    encoding: Option<Encoding>,
+    compression: bool,
+}
 
+/// The encodings that packets can be sent in. Clients can choose one using the
+/// websocket subprotocol otherwise the type of their first message is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Binary,
+    Json,
+}
+
+impl Encoding {
+    pub const PROTOCOLS: &'static [&'static str] = &["quizler.binary", "quizler.json"];
+
+    pub fn from_request(req: &HttpRequest) -> Option<Encoding> {
+        let protocols = req.headers().get(header::SEC_WEBSOCKET_PROTOCOL)?.to_str().ok()?;
+        protocols.split(',')
+            .map(str::trim)
+            .find_map(|protocol| match protocol {
+                "quizler.binary" => Some(Encoding::Binary),
+                "quizler.json" => Some(Encoding::Json),
+                _ => None,
+            })
+    }
+}
+
+/// The identity of the client behind a connection. The id is persisted in a
+/// cookie so that it survives the client reconnecting.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: Identifier,
+    pub address: Option<IpAddr>,
+}
+
+impl Session {
+    const ID_LENGTH: usize = 16;
+    const COOKIE_NAME: &'static str = "quizler_session";
+
+    pub fn from_request(req: &HttpRequest) -> Session {
+        let id = req.cookie(Session::COOKIE_NAME)
+            .map(|cookie| cookie.value().to_string())
+            .filter(|id| id.len() == Session::ID_LENGTH && id.chars().all(|c| c.is_ascii_hexdigit()))
+            .unwrap_or_else(|| random_identifier(Session::ID_LENGTH));
+        Session {
+            id,
+            address: client_address(req),
+        }
+    }
+
+    pub fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(Session::COOKIE_NAME, self.id.clone())
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .permanent()
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct GameData {
+    /// The session id of the connection, recorded as the actor of host actions
+    pub session: Identifier,
     pub hosting: bool,
     pub player_id: Option<Identifier>,
     pub game_id: Option<Identifier>,
 }
 
 impl GameData {
+    /// The log context describing the game and player
+    pub fn log_context(&self) -> LogContext {
+        LogContext {
+            game: self.game_id.clone(),
+            player: self.player_id.clone(),
+            ..LogContext::default()
+        }
+    }
+
     fn reset(&mut self) {
         self.hosting = false;
         self.player_id = None;
@@ -32,93 +133,357 @@ impl GameData {
 
 impl Actor for Connection {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.connection_opened();
+        ctx.run_interval(Connection::HEARTBEAT_INTERVAL, |act, ctx| {
+            if act.heartbeat.get().elapsed() > Connection::CLIENT_TIMEOUT {
+                let _log = act.log_context().enter();
+                info!("Connection timed out");
+                ctx.stop();
+            } else {
+                ctx.ping(b"");
+            }
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        let _log = self.log_context().enter();
+        self.metrics.connection_closed();
+        if let Some(game) = &self.game {
+            game.do_send(GameAction::DoStateChange {
+                state: StateChange::Disconnect,
+                game_data: self.game_data.clone(),
+            });
+        }
+    }
 }
 
 type CContext = <Connection as Actor>::Context;
 
 impl Connection {
-    pub fn new(manager: Addr<GameManager>) -> Connection {
+    pub fn new(manager: Addr<GameManager>, session: Session, address_limiter: AddressLimiter, metrics: Metrics, encoding: Option<Encoding>) -> Connection {
         Connection {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
             game_data: GameData {
+                session: session.id.clone(),
                 player_id: None,
                 game_id: None,
                 hosting: false,
             },
+            game: None,
             manager,
+            session,
+            limiter: ConnectionLimiter::new(),
+            address_limiter,
+            metrics,
+            malformed_packets: TokenBucket::new(Connection::MAX_MALFORMED_PACKETS, 1.0 / 30.0),
+            heartbeat: Rc::new(Cell::new(Instant::now())),
+            protocol_version: None,
+            encoding,
+            compression: false,
         }
     }
 
-    fn packet<W: Writable>(&self, ctx: &mut CContext, mut packet: W) {
-        let mut out = Vec::new();
-        match packet.write(&mut out) {
-            Ok(_) => ctx.binary(out),
-            Err(err) => error!("Failed to write packet {:?}", err),
-        };
+    /// Wraps the request payload so that any data received from the client
+    /// keeps the connection alive. Clients uploading a large frame such as
+    /// CreateGame can't answer pings until the whole frame has been sent.
+    pub fn track_activity<S: Stream + Unpin>(&self, stream: S) -> ActivityStream<S> {
+        ActivityStream {
+            inner: stream,
+            heartbeat: self.heartbeat.clone(),
+        }
     }
 
-    fn handle_action(res: Result<ClientAction, MailboxError>, act: &mut Connection, ctx: &mut CContext) -> Ready<()> {
-        match res {
-            Ok(res) => match res {
-                ClientAction::CreatedGame { id, title } => {
-                    act.game_data.hosting= true;
-                    act.game_data.game_id = Some(id.clone());
-                    act.packet(ctx, ServerPackets::JoinedGame {
-                        owner: true,
-                        id: id.clone(),
-                        title: title.clone(),
-                    });
-                    act.packet(ctx, ServerPackets::GameState { state: GameState::Waiting });
-                    info!("Created new game {} ({})", title, id)
-                }
-                ClientAction::NameTakenResult(result) => act.packet(ctx, ServerPackets::NameTakenResult { result }),
-                ClientAction::Packet(packet) => {
-                    debug!("-> {:?}", packet);
-                    act.packet(ctx, packet);
-                }
-                ClientAction::Error(msg) => act.packet(ctx, ServerPackets::Error { cause: String::from(msg) }),
-                ClientAction::JoinedGame { id, player_id, title } => {
-                    act.game_data.player_id = Some(player_id);
-                    act.game_data.game_id = Some(id.clone());
-                    act.packet(ctx, ServerPackets::JoinedGame {
-                        owner: false,
-                        id,
-                        title,
-                    })
-                }
-                ClientAction::StateChange(state) => {
-                    act.manager.send(ServerAction::DoStateChange {
-                        state,
-                        game_data: act.game_data.clone(),
-                    })
-                        .into_actor(act)
-                        .then(Connection::handle_action)
-                        .wait(ctx);
+    /// The log context for events while handling this connection
+    fn log_context(&self) -> LogContext {
+        LogContext {
+            connection: Some(self.id),
+            session: Some(session_hash(&self.session.id)),
+            ..self.game_data.log_context()
+        }
+    }
+
+    fn handle_packet(&mut self, ctx: &mut CContext, packet: ClientPackets) {
+        match packet {
+            ClientPackets::Handshake { version } => self.handshake(ctx, version.0),
+            _ if self.protocol_version.is_none() => {
+                self.close(ctx, ws::CloseCode::Protocol, "Your client is out of date. Please refresh the page");
+            }
+            ClientPackets::SetCompression { enabled } => self.set_compression(ctx, enabled),
+            // Packets for the game the client is in go straight to that game
+            ClientPackets::StateChange { state } => {
+                self.send_game_action(ctx, GameAction::DoStateChange { state, game_data: self.game_data.clone() });
+            }
+            ClientPackets::Kick { id } => {
+                self.send_game_action(ctx, GameAction::TryKick { id, game_data: self.game_data.clone() });
+            }
+            ClientPackets::RequestBans {} => {
+                self.send_game_action(ctx, GameAction::GetBans { game_data: self.game_data.clone() });
+            }
+            ClientPackets::Unban { id } => {
+                self.send_game_action(ctx, GameAction::TryUnban { id, game_data: self.game_data.clone() });
+            }
+            ClientPackets::Answer { id } => {
+                self.send_game_action(ctx, GameAction::TryAnswer { answer: id, game_data: self.game_data.clone() });
+            }
+            ClientPackets::RequestResults { format } => {
+                self.send_game_action(ctx, GameAction::GetResults { format, game_data: self.game_data.clone() });
+            }
+            // Connections can only be in one game at a time
+            ClientPackets::CreateGame { .. }
+            | ClientPackets::CreateGameFromQuiz { .. }
+            | ClientPackets::RequestJoin { .. }
+            | ClientPackets::ResumeGame { .. } if self.game.as_ref().is_some_and(|game| game.connected()) => {
+                self.packet(ctx, ServerPackets::Error { cause: String::from("You are already in a game.") });
+            }
+            packet => {
+                let ret = ctx.address();
+                self.send_action(ctx, ServerAction {
+                    packet,
+                    ret,
+                    session: self.session.clone(),
+                });
+            }
+        }
+    }
+
+    /// Handles the handshake packet from the client accepting the connection
+    /// if the client is using a supported protocol version
+    fn handshake(&mut self, ctx: &mut CContext, version: u32) {
+        let accepted = (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version);
+        self.packet(ctx, ServerPackets::HandshakeResult {
+            accepted,
+            min_version: VarInt(MIN_PROTOCOL_VERSION),
+            max_version: VarInt(PROTOCOL_VERSION),
+        });
+        if accepted {
+            debug!("Using protocol version {}", version);
+            self.protocol_version = Some(version);
+        } else {
+            self.close(ctx, ws::CloseCode::Protocol, "Unsupported protocol version. Please refresh the page");
+        }
+    }
+
+    /// The number of malformed packets allowed in a burst, one more is
+    /// allowed every 30 seconds
+    const MAX_MALFORMED_PACKETS: u32 = 4;
+    const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+
+    /// Reports a malformed packet back to the client and closes the connection
+    /// if the client has sent too many of them.
+    fn malformed(&mut self, ctx: &mut CContext, cause: String) {
+        self.metrics.decode_error();
+        warn!("Malformed packet: {}", cause);
+        if !self.malformed_packets.take() {
+            self.close(ctx, ws::CloseCode::Invalid, "Too many malformed packets");
+        } else {
+            self.packet(ctx, ServerPackets::Error { cause });
+        }
+    }
+
+    /// Sends the client a disconnect packet with the provided reason and then
+    /// closes the connection.
+    fn close(&mut self, ctx: &mut CContext, code: ws::CloseCode, reason: &str) {
+        info!(address = ?self.session.address, "Closing connection: {}", reason);
+        self.packet(ctx, ServerPackets::Disconnect { reason: String::from(reason) });
+        ctx.close(Some(ws::CloseReason {
+            code,
+            description: Some(String::from(reason)),
+        }));
+        ctx.stop();
+    }
+
+    /// Checks the rate limits for the provided packet and handles limited
+    /// packets. Returns whether the packet should be processed.
+    fn check_limits(&mut self, ctx: &mut CContext, packet: &ClientPackets) -> bool {
+        let mut result = self.limiter.check(packet);
+        if result == LimitResult::Allowed && LimitClass::of(packet) == LimitClass::CreateGame {
+            if let Some(address) = self.session.address {
+                if !self.address_limiter.check_create_game(address) {
+                    result = self.limiter.violation();
                 }
-                ClientAction::Disconnect => {
-                    act.game_data.reset();
+            }
+        }
+        self.handle_limit(ctx, result)
+    }
+
+    fn handle_limit(&mut self, ctx: &mut CContext, result: LimitResult) -> bool {
+        match result {
+            LimitResult::Allowed => true,
+            LimitResult::Limited => {
+                self.packet(ctx, ServerPackets::Error { cause: String::from("You are doing that too fast") });
+                false
+            }
+            LimitResult::Abusive => {
+                warn!(address = ?self.session.address, "Disconnecting for exceeding rate limits");
+                self.close(ctx, ws::CloseCode::Policy, "Too many requests");
+                false
+            }
+        }
+    }
+
+    fn packet(&self, ctx: &mut CContext, mut packet: ServerPackets) {
+        self.metrics.packet_out(packet.name());
+        if self.encoding == Some(Encoding::Json) {
+            ctx.text(json::encode(&packet));
+            return;
+        }
+        let mut out = Vec::new();
+        if let Err(err) = packet.write(&mut out) {
+            error!("Failed to write packet {:?}", err);
+            return;
+        }
+        if self.compression {
+            if let Some(data) = compress(&out) {
+                let mut compressed = ServerPackets::Compressed { data: Blob(data) };
+                let mut compressed_out = Vec::new();
+                if compressed.write(&mut compressed_out).is_ok() {
+                    out = compressed_out;
                 }
-                ClientAction::Multiple(actions) => {
-                    for action in actions {
-                        Connection::handle_action(Ok(action), act, ctx);
-                    }
+            }
+        }
+        ctx.binary(out);
+    }
+
+    /// Enables or disables compression of large packets for this connection.
+    /// Compression is only supported when using the binary encoding.
+    fn set_compression(&mut self, ctx: &mut CContext, enabled: bool) {
+        self.compression = enabled && self.encoding == Some(Encoding::Binary);
+        self.packet(ctx, ServerPackets::SetCompression { enabled: self.compression });
+    }
+
+    /// Checks whether the client is using the provided encoding. The encoding
+    /// is decided by the first message if it wasn't chosen using a subprotocol.
+    fn accepts(&mut self, encoding: Encoding) -> bool {
+        *self.encoding.get_or_insert(encoding) == encoding
+    }
+
+    fn received(&mut self, ctx: &mut CContext, packet: Result<ClientPackets, DecodeError>) {
+        let packet = match packet {
+            Ok(ClientPackets::Compressed { data }) => decompress(&data)
+                .and_then(|bytes| decode_client_packet(&bytes))
+                .and_then(|packet| match packet {
+                    ClientPackets::Compressed { .. } => Err(DecodeError::InvalidCompression),
+                    packet => Ok(packet),
+                }),
+            packet => packet,
+        };
+        match packet {
+            Ok(packet) => {
+                LogContext::set_packet(packet.name());
+                debug!("<- {:?}", packet);
+                self.metrics.packet_in(packet.name());
+                if self.check_limits(ctx, &packet) {
+                    self.handle_packet(ctx, packet);
                 }
-                ClientAction::None => {}
-                ClientAction::BeginKick(id) => {
-                    act.manager.send(ServerAction::TryKick {
-                        game_data: act.game_data.clone(),
-                        id
-                    })
-                        .into_actor(act)
-                        .then(Connection::handle_action)
-                        .wait(ctx);
+            }
+            Err(err) => self.malformed(ctx, err.to_string()),
+        }
+    }
+
+    /// Sends an action to the game manager and handles its result once it
+    /// responds, recording how long the manager took to respond.
+    fn send_action(&mut self, ctx: &mut CContext, action: ServerAction) {
+        self.send_to(ctx, self.manager.clone().recipient(), action, |_act, ctx, err| {
+            error!("Game manager failed to respond: {:?}", err);
+            ctx.stop()
+        });
+    }
+
+    /// Sends an action to the game this connection is in and handles its
+    /// result once it responds. Games that have ended no longer respond.
+    fn send_game_action(&mut self, ctx: &mut CContext, action: GameAction) {
+        let Some(game) = self.game.clone() else {
+            self.packet(ctx, ServerPackets::Error { cause: String::from("You are not in a game.") });
+            return;
+        };
+        self.send_to(ctx, game.recipient(), action, |act, ctx, _err| {
+            act.game = None;
+            act.game_data.reset();
+            act.packet(ctx, ServerPackets::Error { cause: String::from("You are not in a game.") });
+        });
+    }
+
+    fn send_to<M, F>(&mut self, ctx: &mut CContext, recipient: Recipient<M>, action: M, failed: F)
+        where M: Message<Result = ClientAction> + Send + 'static,
+              F: FnOnce(&mut Connection, &mut CContext, MailboxError) + 'static {
+        let start = Instant::now();
+        recipient.send(action)
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                let _log = act.log_context().enter();
+                act.metrics.mailbox(start.elapsed());
+                // Waiting stops the payload from being read and the heartbeat
+                // from running so the client shouldn't time out because of it
+                act.heartbeat.set(Instant::now());
+                match res {
+                    Ok(action) => act.handle_action(action, ctx),
+                    Err(err) => failed(act, ctx, err),
                 }
+                ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn handle_action(&mut self, action: ClientAction, ctx: &mut CContext) {
+        match action {
+            ClientAction::CreatedGame { id, title, game } => {
+                self.game = Some(game);
+                self.game_data.hosting = true;
+                self.game_data.game_id = Some(id.clone());
+                self.packet(ctx, ServerPackets::JoinedGame {
+                    owner: true,
+                    id: id.clone(),
+                    title: title.clone(),
+                });
+                self.packet(ctx, ServerPackets::GameState { state: GameState::Waiting });
+                info!(game = id.as_str(), "Created new game {}", title)
             }
-            Err(err) => {
-                error!("err {:?}", err);
-                ctx.stop()
+            ClientAction::NameTakenResult(reason) => self.packet(ctx, ServerPackets::NameTakenResult {
+                result: reason.is_some(),
+                reason: reason.map(String::from).unwrap_or_default(),
+            }),
+            ClientAction::Packet(packet) => {
+                debug!("-> {:?}", packet);
+                self.packet(ctx, packet);
             }
+            ClientAction::Error(msg) => self.packet(ctx, ServerPackets::Error { cause: String::from(msg) }),
+            ClientAction::JoinedGame { id, player_id, title, game } => {
+                self.game = Some(game);
+                self.game_data.hosting = false;
+                self.game_data.player_id = Some(player_id);
+                self.game_data.game_id = Some(id.clone());
+                self.packet(ctx, ServerPackets::JoinedGame {
+                    owner: false,
+                    id,
+                    title,
+                })
+            }
+            ClientAction::ResumedGame { id, player_id, title, game } => {
+                self.game = Some(game);
+                let owner = player_id.is_none();
+                self.game_data.hosting = owner;
+                self.game_data.player_id = player_id;
+                self.game_data.game_id = Some(id.clone());
+                self.packet(ctx, ServerPackets::JoinedGame {
+                    owner,
+                    id,
+                    title,
+                })
+            }
+            ClientAction::Disconnect => {
+                self.game = None;
+                self.game_data.reset();
+            }
+            ClientAction::Multiple(actions) => {
+                for action in actions {
+                    self.handle_action(action, ctx);
+                }
+            }
+            ClientAction::None => {}
         }
-        ready(())
     }
 }
 
@@ -126,32 +491,68 @@ impl Handler<ClientAction> for Connection {
     type Result = ();
 
     fn handle(&mut self, msg: ClientAction, ctx: &mut Self::Context) -> Self::Result {
-        Connection::handle_action(Ok(msg), self, ctx);
+        let _log = self.log_context().enter();
+        self.handle_action(msg, ctx);
     }
 }
 
 
+/// The request payload of a connection which records when data was last
+/// received from the client
+pub struct ActivityStream<S> {
+    inner: S,
+    heartbeat: Rc<Cell<Instant>>,
+}
+
+impl<S: Stream + Unpin> Stream for ActivityStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.heartbeat.set(Instant::now());
+        }
+        poll
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Connection {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let _log = self.log_context().enter();
+        // Control frames keep the connection alive so they don't count towards
+        // the frames shared between every connection from the address
+        let control = matches!(msg, Ok(ws::Message::Ping(_) | ws::Message::Pong(_) | ws::Message::Close(_)));
+        if let Some(address) = self.session.address.filter(|_| !control) {
+            if !self.address_limiter.check_frame(address) {
+                let result = self.limiter.violation();
+                if !self.handle_limit(ctx, result) {
+                    return;
+                }
+            }
+        }
         match msg {
             Ok(ws::Message::Ping(msg)) => { ctx.pong(&msg); }
-            Ok(ws::Message::Text(text)) => { warn!("Received text message \"{}\".. dont know what to do with it.",text); }
+            Ok(ws::Message::Text(text)) => {
+                if self.accepts(Encoding::Json) {
+                    self.received(ctx, json::decode(&text));
+                } else {
+                    self.malformed(ctx, String::from("Expected a binary message"));
+                }
+            }
             Ok(ws::Message::Binary(bin)) => {
-                let mut cursor = Cursor::new(bin.to_vec());
-                match ClientPackets::read(&mut cursor) {
-                    Ok(packet) => {
-                        debug!("<- {:?}", packet);
-                        let ret = ctx.address().clone();
-                        self.manager.send(ServerAction::Packet {
-                            packet,
-                            ret,
-                        })
-                            .into_actor(self)
-                            .then(Connection::handle_action)
-                            .wait(ctx);
-                    }
-                    Err(_) => {}
+                if self.accepts(Encoding::Binary) {
+                    self.received(ctx, decode_client_packet(&bin));
+                } else {
+                    self.malformed(ctx, String::from("Expected a JSON text message"));
+                }
+            }
+            Err(err) => {
+                warn!(address = ?self.session.address, "Websocket protocol error: {}", err);
+                let code = match err {
+                    ws::ProtocolError::Overflow => ws::CloseCode::Size,
+                    _ => ws::CloseCode::Protocol,
                 };
+                self.close(ctx, code, "Invalid message");
             }
             _ => (),
         };
@@ -1,8 +1,13 @@
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use actix_web::HttpRequest;
 use rand::{Rng, thread_rng};
+use sha2::{Digest, Sha256};
 
 pub type Identifier = String;
 
-const IDENTIFIER_CHARS: &'static [char; 16] = &['A', 'B', 'C', 'D', 'E', 'F', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
+const IDENTIFIER_CHARS: &[char; 16] = &['A', 'B', 'C', 'D', 'E', 'F', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9'];
 
 pub fn random_identifier(length: usize) -> Identifier {
     let mut out = String::with_capacity(length);
@@ -13,3 +18,44 @@ pub fn random_identifier(length: usize) -> Identifier {
     }
     out
 }
+
+/// Hashes a session id into a short identifier for logs. Session ids can't be
+/// recorded themselves because they authorize the host of a game.
+pub fn session_hash(session: &str) -> String {
+    let digest = Sha256::digest(session.as_bytes());
+    let mut out = String::with_capacity(12);
+    for byte in &digest[..6] {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Reads and parses the environment variable with the provided key falling
+/// back to the default value if it's missing or invalid
+pub fn env_value<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads a boolean flag from the environment variable with the provided key
+pub fn env_flag(key: &str) -> bool {
+    matches!(std::env::var(key).as_deref(), Ok("1") | Ok("true") | Ok("yes"))
+}
+
+/// Determines the IP address of the client that made the provided request. The
+/// forwarded headers are only used when QUIZLER_TRUST_PROXY is enabled because
+/// they can be set to anything by the client otherwise.
+pub fn client_address(req: &HttpRequest) -> Option<IpAddr> {
+    if env_flag("QUIZLER_TRUST_PROXY") {
+        let info = req.connection_info();
+        let address = info.realip_remote_addr()?;
+        address.parse::<SocketAddr>()
+            .map(|address| address.ip())
+            .or_else(|_| address.parse::<IpAddr>())
+            .ok()
+    } else {
+        req.peer_addr().map(|address| address.ip())
+    }
+}
@@ -1,21 +1,32 @@
 import {
     AnswerResultPacket,
+    BanListPacket,
+    CompressedClientPacket,
     DisconnectPacket,
     ErrorPacket,
     GameStatePacket,
+    HandshakePacket,
+    HandshakeResultPacket,
     JoinGamePacket,
     KickPacket,
     NameTakenResultPacket,
     PlayerDataMode,
     PlayerDataPacket,
+    PROTOCOL_VERSION,
     QuestionPacket,
+    QuizSavedPacket,
+    RedirectPacket,
+    RequestBansPacket,
+    ResultsPacket,
+    ResumeGamePacket,
     ScoresPacket,
     StateChangePacket,
     States,
-    TimeSyncPacket
+    TimeSyncPacket,
+    UnbanPacket
 } from "./packets";
 import { onUnmounted, reactive, ref, Ref, watch } from "vue";
-import { dialog, toast } from "@/tools/ui";
+import { dialog, loading, toast } from "@/tools/ui";
 import { DEBUG, HOST } from "@/constants";
 import { router } from "@/router";
 import { useRouter } from "vue-router";
@@ -42,6 +53,12 @@ export interface PlayerData {
     score: number;
 }
 
+// Structure of a player banned from the game
+export interface BanData {
+    id: string;
+    name: string;
+}
+
 // Structure of game details
 export interface GameData {
     owner: boolean;
@@ -61,6 +78,13 @@ export interface QuestionData {
 // QuestionData with the values array (Used only for creation of questions)
 export type QuestionDataWithValues = { values: number[] } & QuestionData;
 
+// Structure of the question currently being asked in a game
+export interface GameQuestion {
+    imageUrl?: string;
+    question: string;
+    answers: string[];
+}
+
 /**
  * Stores all logic for communicating between the client and server over the
  * websocket connection.
@@ -73,9 +97,10 @@ export class Client {
     open = ref(false); // The open state of the web socket connection
     gameData = ref<GameData | null>(null); // The current game data
     players = reactive<PlayerMap>({}); // The map of players to their names
-    question = ref<QuestionData | null>(null); // The active question in the game (store here to persist)
+    question = ref<GameQuestion | null>(null); // The active question in the game (store here to persist)
     gameState = ref<GameState>(GameState.UNSET); // The current game state
     self = ref<PlayerData | null>(null); // The player we are playing as
+    bans = ref<BanData[]>([]); // The players banned from the game (host only)
 
     /**
      * Creates a new socket instance
@@ -87,6 +112,10 @@ export class Client {
         // Add event listener for when the socket connection is open
         socket.addEventListener('open', () => {
             if (DEBUG) console.debug('Connected to socket server'); // Debug logging
+            socket.send(HandshakePacket, {version: PROTOCOL_VERSION}); // Tell the server our protocol version
+            if (this.gameData.value) { // If we were in a game before reconnecting (e.g. the server restarted)
+                socket.send(ResumeGamePacket, {id: this.gameData.value.id}); // Ask to resume the game
+            }
             this.open.value = true; // Update the open state
         });
         // Add event listener for when the socket connection is closed
@@ -98,7 +127,15 @@ export class Client {
             DisconnectPacket, ErrorPacket, JoinGamePacket,
             NameTakenResultPacket, GameStatePacket, PlayerDataPacket,
             TimeSyncPacket, QuestionPacket, AnswerResultPacket, ScoresPacket,
+            HandshakeResultPacket, QuizSavedPacket, ResultsPacket, RedirectPacket,
+            BanListPacket,
         );
+        // Add a listener for the Handshake Result packet
+        socket.addListener(HandshakeResultPacket, ({accepted}) => {
+            if (!accepted) { // If the server doesn't support our protocol version
+                dialog('Outdated', 'This version of Quizler is out of date. Please refresh the page');
+            }
+        });
         // Add a listener for the Disconnect packet
         socket.addListener(DisconnectPacket, async ({reason}) => {
             if (this.gameState.value !== GameState.STOPPED) {
@@ -107,6 +144,13 @@ export class Client {
             this.resetState(); // Reset the state
             await router.push({name: 'Home'}); // Return to the home page
         });
+        // Add a listener for the Redirect packet (the game is hosted by another server)
+        socket.addListener(RedirectPacket, ({id, url}) => {
+            loading(true, 'Connecting'); // Display a loader while the other server loads
+            this.resetState(); // The game data doesn't belong to this server
+            // Open the join page on the server that owns the game
+            window.location.href = new URL(`/join?code=${id}`, url || window.location.origin).toString();
+        });
         // Add a listener for the Error packet
         socket.addListener(ErrorPacket, ({cause}) => {
             console.error(`An error occurred ${cause}`); // Print the error to the console
@@ -132,8 +176,10 @@ export class Client {
             }
         });
         // Add a listener for the Question packet
-        socket.addListener(QuestionPacket, (data: QuestionData) => {
-            this.question.value = data; // Set the question to the provided data
+        socket.addListener(QuestionPacket, ({image, question, answers}) => {
+            // Images are served over HTTP by the server so resolve the image path against the server
+            const imageUrl = image ? new URL(image, host.replace(/^ws/, 'http')).toString() : undefined;
+            this.question.value = {imageUrl, question, answers}; // Set the question to the provided data
         });
         // Add a listener for the Scores packet
         socket.addListener(ScoresPacket, ({scores}) => {
@@ -144,6 +190,8 @@ export class Client {
                 }
             }
         });
+        // Add a listener for the BanList packet (sent to the host when the bans change)
+        socket.addListener(BanListPacket, ({bans}) => this.bans.value = bans);
         // Set the socket instance
         this.socket = socket;
     }
@@ -156,6 +204,7 @@ export class Client {
         this.gameData.value = null;
         this.question.value = null;
         this.gameState.value = GameState.UNSET;
+        this.bans.value = [];
         for (let key in this.players) {
             delete this.players[key];
         }
@@ -187,6 +236,53 @@ export class Client {
         delete this.players[id]; // Remove the player for the map
         this.socket.send(KickPacket, {id}); // Send a kick player packet
     }
+
+    /**
+     * Sends a large packet (such as CreateGame) wrapped in a Compressed packet
+     * so that question images upload faster over slow connections. Sends the
+     * packet uncompressed if the browser can't compress it or compressing
+     * didn't make it any smaller
+     *
+     * @param definition The definition of the packet to send
+     * @param data The packet data
+     */
+    async sendCompressed<D extends StructLayout>(definition: PacketDefinition<D>, data: StructTyped<D>) {
+        const buffer = this.socket.createBuffer(definition, data); // Encode the packet
+        if (typeof CompressionStream === 'undefined') { // If the browser can't compress
+            this.socket.sendBuffer(buffer); // Send the packet as is
+            return;
+        }
+        // Compress the encoded packet using zlib (the "deflate" format)
+        const stream = new Blob([buffer]).stream().pipeThrough(new CompressionStream('deflate'));
+        const compressed = new Uint8Array(await new Response(stream).arrayBuffer());
+        if (compressed.length < buffer.byteLength) { // If compressing made the packet smaller
+            this.socket.send(CompressedClientPacket, {data: compressed}); // Send the compressed packet
+        } else {
+            this.socket.sendBuffer(buffer); // Send the packet as is
+        }
+    }
+
+    /**
+     * Asks the server for the list of banned players (HOST ONLY)
+     */
+    requestBans() {
+        this.socket.send(RequestBansPacket, {}); // Send a request bans packet
+    }
+
+    /**
+     * Allows a banned player to join the game again (HOST ONLY)
+     *
+     * @param id The id of the banned player
+     */
+    unban(id: string) {
+        const ban = this.bans.value.find(ban => ban.id === id);
+        if (ban) { // If the player is banned
+            // Display a toast with the unbanned message
+            toast(`Unbanned player "${ban.name}"`);
+        }
+        if (DEBUG) console.debug('Unbanned player ' + id); // Print debug unbanned message
+        this.socket.send(UnbanPacket, {id}); // Send an unban player packet
+    }
 }
 
 // The socket instance
@@ -11,6 +11,10 @@ import {
     VarInt
 } from "wsbps-js"
 
+// The version of the packet protocol this client speaks. This must match
+// PROTOCOL_VERSION in the backend packets.rs
+export const PROTOCOL_VERSION = 2;
+
 // The different possible values for player data packet modes
 export enum PlayerDataMode {ADD, REMOVE, SELF}
 
@@ -18,37 +22,67 @@ export enum PlayerDataMode {ADD, REMOVE, SELF}
 // from the server
 export enum States {DISCONNECT, START, SKIP}
 
+// The formats that game results can be downloaded in
+export enum ResultsFormat {JSON, CSV}
+
 // SERVER PACKETS
 export const DisconnectPacket = new PacketDefinition(0x00, {reason: Str}, ['reason']);
 export const ErrorPacket = new PacketDefinition(0x01, {cause: Str}, ['cause']);
 export const JoinGamePacket = new PacketDefinition(0x02, {id: Str, owner: bool, title: Str}, ['id', 'owner', 'title']);
-export const NameTakenResultPacket = new PacketDefinition(0x03, {result: bool}, ['result']);
+export const NameTakenResultPacket = new PacketDefinition(0x03, {result: bool, reason: Str}, ['result', 'reason']);
 export const GameStatePacket = new PacketDefinition(0x04, {state: u8}, ['state']);
 export const PlayerDataPacket = new PacketDefinition(0x05, {id: Str, name: Str, mode: u8}, ['id', 'name', 'mode']);
 export const TimeSyncPacket = new PacketDefinition(0x06, {total: VarInt, remaining: VarInt}, ['total', 'remaining']);
 export const QuestionPacket = new PacketDefinition(0x07, {
-    imageType: Str,
-    image: ByteArray,
+    image: Str,
     question: Str,
     answers: Vec(Str),
 }, ['image', 'question', 'answers']);
 export const AnswerResultPacket = new PacketDefinition(0x08, {result: bool}, ['result']);
 export const ScoresPacket = new PacketDefinition(0x09, {scores: MapType(Str, u32)}, ['scores']);
+export const BanListPacket = new PacketDefinition(0x0A, {
+    bans: StructVec({id: Str, name: Str}, ['id', 'name'])
+}, ['bans']);
+export const CompressedPacket = new PacketDefinition(0x0B, {data: ByteArray}, ['data']);
+export const SetCompressionResultPacket = new PacketDefinition(0x0C, {enabled: bool}, ['enabled']);
+export const QuizSavedPacket = new PacketDefinition(0x0D, {code: Str}, ['code']);
+export const ResultsPacket = new PacketDefinition(0x0E, {format: u8, data: Str}, ['format', 'data']);
+export const RedirectPacket = new PacketDefinition(0x0F, {id: Str, url: Str}, ['id', 'url']);
+export const HandshakeResultPacket = new PacketDefinition(0x7F, {
+    accepted: bool,
+    minVersion: VarInt,
+    maxVersion: VarInt
+}, ['accepted', 'minVersion', 'maxVersion']);
 
 // CLIENT PACKETS
+// The structure of the questions sent when creating or saving a quiz
+const QuestionsType = StructVec({
+    imageType: Str,
+    image: ByteArray,
+    question: Str,
+    answers: Vec(Str),
+    values: Vec(u8)
+}, ['imageType', 'image', 'question', 'answers', 'values']);
+
 export const CreateGamePacket = new PacketDefinition(0x00, {
     title: Str,
-    questions: StructVec({
-        imageType: Str,
-        image: ByteArray,
-        question: Str,
-        answers: Vec(Str),
-        values: Vec(u8)
-    }, ['imageType', 'image', 'question', 'answers', 'values'])
+    questions: QuestionsType
 }, ['title', 'questions']);
 export const CheckNameTakenPacket = new PacketDefinition(0x01, {id: Str, name: Str}, ['id', 'name']);
 export const RequestGameStatePacket = new PacketDefinition(0x02, {id: Str}, ['id']);
 export const RequestJoinPacket = new PacketDefinition(0x03, {id: Str, name: Str}, ['id', 'name']);
 export const StateChangePacket = new PacketDefinition(0x04, {state: u8}, ['state']);
 export const AnswerPacket = new PacketDefinition(0x05, {id: u8}, ['id']);
-export const KickPacket = new PacketDefinition(0x06, {id: Str}, ['id']);
\ No newline at end of file
+export const KickPacket = new PacketDefinition(0x06, {id: Str}, ['id']);
+export const RequestBansPacket = new PacketDefinition(0x07, {}, []);
+export const UnbanPacket = new PacketDefinition(0x08, {id: Str}, ['id']);
+export const SetCompressionPacket = new PacketDefinition(0x09, {enabled: bool}, ['enabled']);
+export const CompressedClientPacket = new PacketDefinition(0x0A, {data: ByteArray}, ['data']);
+export const SaveQuizPacket = new PacketDefinition(0x0B, {
+    title: Str,
+    questions: QuestionsType
+}, ['title', 'questions']);
+export const CreateGameFromQuizPacket = new PacketDefinition(0x0C, {code: Str}, ['code']);
+export const RequestResultsPacket = new PacketDefinition(0x0D, {format: u8}, ['format']);
+export const ResumeGamePacket = new PacketDefinition(0x0E, {id: Str}, ['id']);
+export const HandshakePacket = new PacketDefinition(0x7F, {version: VarInt}, ['version']);
\ No newline at end of file
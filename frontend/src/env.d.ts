@@ -28,6 +28,11 @@ declare module '*.svg?inline' {
     export default component;
 }
 
+// Compression streams aren't included in the DOM types of this TypeScript version
+declare class CompressionStream extends TransformStream<Uint8Array, Uint8Array> {
+    constructor(format: 'deflate' | 'deflate-raw' | 'gzip');
+}
+
 declare module 'Base64' {
     export function btoa(input: string): string;
 
@@ -1,21 +1,43 @@
-import { btoa } from "Base64";
+import { atob, btoa } from "Base64";
 
 /**
- * Converts the provided Uint8Array into a base64 data url
- * string of the array contents allowing it to be used as
- * the src for an image tag
+ * Encodes the contents of the provided Uint8Array as a base64 string
  *
- * @param type The type of data stored in the array (e.g. image/png)
- * @param array The array of binary data for the image
+ * @param array The array of binary data to encode
  */
-export function arrayToDataUrl(type: string, array: Uint8Array): string {
+export function arrayToBase64(array: Uint8Array): string {
     const fch = String.fromCharCode; // Store the from char code function as a shorthand
     const out: string[] = []; // Empty array of all the char values
     for (let elm of array) {
         out.push(fch(elm)); // Convert the array element to a string and append to array
     }
     // Base64 encode the string
-    const base64: string = btoa(out.join('') /* Join all the strings to one big string*/);
+    return btoa(out.join('') /* Join all the strings to one big string*/);
+}
+
+/**
+ * Decodes the provided base64 string into a Uint8Array
+ *
+ * @param base64 The base64 string to decode
+ */
+export function base64ToArray(base64: string): Uint8Array {
+    const raw: string = atob(base64);
+    const out = new Uint8Array(raw.length);
+    for (let i = 0; i < raw.length; i++) {
+        out[i] = raw.charCodeAt(i);
+    }
+    return out;
+}
+
+/**
+ * Converts the provided Uint8Array into a base64 data url
+ * string of the array contents allowing it to be used as
+ * the src for an image tag
+ *
+ * @param type The type of data stored in the array (e.g. image/png)
+ * @param array The array of binary data for the image
+ */
+export function arrayToDataUrl(type: string, array: Uint8Array): string {
     // Append the data url parts and return the data
-    return `data:${type};base64,${base64}`
-}
\ No newline at end of file
+    return `data:${type};base64,${arrayToBase64(array)}`
+}